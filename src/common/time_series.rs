@@ -5,7 +5,6 @@ use burn::{
     module::Module,
     nn::{
         gru::{Gru, GruConfig},
-        loss::{MSELoss, Reduction},
         Dropout, DropoutConfig, LayerNorm, LayerNormConfig, Linear, LinearConfig,
     },
     tensor::{backend::Backend, Tensor},
@@ -143,8 +142,8 @@ impl<B: Backend> TrainableModel<B, RegressionOutput<B>> for GruNetwork<B> {
     type Batch = RegressionBatch<B, 3, 2>;
 
     fn forward_training(&self, batch: Self::Batch) -> RegressionOutput<B> {
-        let output = self.forward(batch.inputs);
-        let loss = MSELoss::new().forward(output.clone(), batch.targets.clone(), Reduction::Auto);
+        let output = self.forward(batch.inputs.clone());
+        let loss = batch.mse_loss(output.clone());
         RegressionOutput::new(loss, output, batch.targets)
     }
 }
@@ -0,0 +1,286 @@
+use std::f32::consts::TAU;
+
+use burn::tensor::{backend::Backend, Data, Shape, Tensor};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::format::{fnv1a, FNV_OFFSET};
+
+/// Random changes made to the inputs of each training item as it is batched. Inputs are treated
+/// as `[steps, features]` series.
+///
+/// Each probability is the chance that an item gets that augmentation, so the defaults of 0 leave
+/// every item unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Augmentations {
+    /// Adds Gaussian noise to every value
+    pub jitter_probability: f64,
+    pub jitter_std_dev: f32,
+    /// Multiplies each feature by a random factor around 1
+    pub scaling_probability: f64,
+    pub scaling_std_dev: f32,
+    /// Speeds up and slows down time smoothly, with random speeds around 1 at evenly spaced knots
+    pub time_warp_probability: f64,
+    pub time_warp_std_dev: f32,
+    pub time_warp_knots: usize,
+    /// Stretches a random slice of the series back to its full length
+    pub window_slice_probability: f64,
+    /// The length of the slice as a fraction of the series
+    pub window_slice_ratio: f32,
+    /// Zeroes random steps
+    pub mask_probability: f64,
+    /// The chance of each step being zeroed
+    pub mask_ratio: f32,
+}
+
+impl Default for Augmentations {
+    fn default() -> Self {
+        Self {
+            jitter_probability: 0.0,
+            jitter_std_dev: 0.03,
+            scaling_probability: 0.0,
+            scaling_std_dev: 0.1,
+            time_warp_probability: 0.0,
+            time_warp_std_dev: 0.2,
+            time_warp_knots: 4,
+            window_slice_probability: 0.0,
+            window_slice_ratio: 0.9,
+            mask_probability: 0.0,
+            mask_ratio: 0.1,
+        }
+    }
+}
+
+impl Augmentations {
+    pub fn is_enabled(&self) -> bool {
+        [
+            self.jitter_probability,
+            self.scaling_probability,
+            self.time_warp_probability,
+            self.window_slice_probability,
+            self.mask_probability,
+        ]
+        .into_iter()
+        .any(|probability| probability > 0.0)
+    }
+}
+
+/// A sample of the standard normal distribution, using the Box-Muller transform.
+fn normal(rng: &mut impl Rng) -> f32 {
+    let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let v: f32 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+}
+
+/// Reads `item` at the fractional steps `positions`, interpolating linearly between steps.
+fn resample(item: &[f32], features: usize, positions: &[f32]) -> Vec<f32> {
+    let last = item.len() / features - 1;
+    let mut out = Vec::with_capacity(item.len());
+    for &position in positions {
+        let position = position.clamp(0.0, last as f32);
+        let before = position.floor() as usize;
+        let after = (before + 1).min(last);
+        let t = position - before as f32;
+        for feature in 0..features {
+            let a = item[before * features + feature];
+            let b = item[after * features + feature];
+            out.push(a + (b - a) * t);
+        }
+    }
+    out
+}
+
+/// Applies [`Augmentations`] to batches.
+///
+/// Each batch draws from its own generator, seeded from `seed` and the contents of the batch, so
+/// the augmentations do not depend on which loader thread batches it or when. The same batch is
+/// augmented the same way every time, so training data should be shuffled for augmentations to
+/// vary between epochs.
+pub struct Augmenter {
+    augmentations: Augmentations,
+    seed: u64,
+}
+
+impl Augmenter {
+    pub fn new(augmentations: Augmentations, seed: u64) -> Self {
+        Self {
+            augmentations,
+            seed,
+        }
+    }
+
+    pub fn augmentations(&self) -> &Augmentations {
+        &self.augmentations
+    }
+
+    /// Augments each item of inputs of shape `[batch, steps, features]`.
+    pub fn augment<B: Backend>(&self, inputs: Tensor<B, 3>) -> Tensor<B, 3> {
+        let dims = inputs.dims();
+        let [_, steps, features] = dims;
+        let device = inputs.device();
+        let mut values = inputs.into_data().convert::<f32>().value;
+        self.augment_values(&mut values, [steps, features]);
+        Tensor::from_data_device(Data::new(values, Shape::new(dims)).convert(), &device)
+    }
+
+    /// Augments each item of `values`, laid out as `[batch, steps, features]`.
+    pub fn augment_values(&self, values: &mut [f32], [steps, features]: [usize; 2]) {
+        if steps == 0 || features == 0 {
+            return;
+        }
+        let hash = values
+            .iter()
+            .fold(fnv1a(FNV_OFFSET, &self.seed.to_le_bytes()), |hash, x| {
+                fnv1a(hash, &x.to_le_bytes())
+            });
+        let mut rng = SmallRng::seed_from_u64(hash);
+        for item in values.chunks_exact_mut(steps * features) {
+            self.augment_item(item, features, &mut rng);
+        }
+    }
+
+    fn augment_item(&self, item: &mut [f32], features: usize, rng: &mut impl Rng) {
+        let augmentations = &self.augmentations;
+        let steps = item.len() / features;
+
+        if rng.gen_bool(augmentations.time_warp_probability.clamp(0.0, 1.0)) && steps > 1 {
+            let knots = augmentations.time_warp_knots.max(1);
+            let speeds: Vec<f32> = (0..=knots)
+                .map(|_| (1.0 + augmentations.time_warp_std_dev * normal(rng)).max(0.1))
+                .collect();
+            // The time that has passed at each step, with the speed interpolated between knots
+            let mut times = vec![0.0f32; steps];
+            for step in 1..steps {
+                let knot = step as f32 / (steps - 1) as f32 * knots as f32;
+                let before = (knot.floor() as usize).min(knots - 1);
+                let t = knot - before as f32;
+                let speed = speeds[before] + (speeds[before + 1] - speeds[before]) * t;
+                times[step] = times[step - 1] + speed;
+            }
+            let scale = (steps - 1) as f32 / times[steps - 1];
+            times.iter_mut().for_each(|time| *time *= scale);
+            item.copy_from_slice(&resample(item, features, &times));
+        }
+
+        if rng.gen_bool(augmentations.window_slice_probability.clamp(0.0, 1.0)) && steps > 1 {
+            let len = ((steps as f32 * augmentations.window_slice_ratio).round() as usize)
+                .clamp(1, steps);
+            let start = rng.gen_range(0..=steps - len) as f32;
+            let stretch = (len - 1) as f32 / (steps - 1) as f32;
+            let positions: Vec<f32> = (0..steps)
+                .map(|step| start + step as f32 * stretch)
+                .collect();
+            item.copy_from_slice(&resample(item, features, &positions));
+        }
+
+        if rng.gen_bool(augmentations.scaling_probability.clamp(0.0, 1.0)) {
+            let factors: Vec<f32> = (0..features)
+                .map(|_| 1.0 + augmentations.scaling_std_dev * normal(rng))
+                .collect();
+            for (j, x) in item.iter_mut().enumerate() {
+                *x *= factors[j % features];
+            }
+        }
+
+        if rng.gen_bool(augmentations.jitter_probability.clamp(0.0, 1.0)) {
+            for x in item.iter_mut() {
+                *x += augmentations.jitter_std_dev * normal(rng);
+            }
+        }
+
+        if rng.gen_bool(augmentations.mask_probability.clamp(0.0, 1.0)) {
+            let ratio = augmentations.mask_ratio.clamp(0.0, 1.0) as f64;
+            for step in item.chunks_exact_mut(features) {
+                if rng.gen_bool(ratio) {
+                    step.fill(0.0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::tensor::Tensor;
+    use burn_ndarray::NdArray;
+
+    use super::{Augmentations, Augmenter};
+
+    fn ramp() -> Tensor<NdArray, 3> {
+        Tensor::<NdArray, 1>::from_floats([0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0])
+            .reshape([1, 5, 2])
+    }
+
+    fn values(inputs: Tensor<NdArray, 3>) -> Vec<f32> {
+        inputs.into_data().value
+    }
+
+    #[test]
+    fn augment_01() {
+        let defaults = Augmentations::default();
+        assert!(!defaults.is_enabled());
+        assert_eq!(
+            values(Augmenter::new(defaults.clone(), 0).augment(ramp())),
+            values(ramp())
+        );
+
+        // Without any change in speed, warping keeps every step in place
+        let warp = Augmentations {
+            time_warp_probability: 1.0,
+            time_warp_std_dev: 0.0,
+            ..defaults.clone()
+        };
+        let warped = values(Augmenter::new(warp, 0).augment(ramp()));
+        for (x, y) in warped.iter().zip(values(ramp())) {
+            assert!((x - y).abs() < 1e-5);
+        }
+
+        // A slice of 3 of the 5 steps, stretched back to 5 steps
+        let slice = Augmentations {
+            window_slice_probability: 1.0,
+            window_slice_ratio: 0.6,
+            ..defaults.clone()
+        };
+        let sliced = values(Augmenter::new(slice, 0).augment(ramp()));
+        let first = sliced[0];
+        assert!([0.0, 2.0, 4.0].contains(&first));
+        for (step, x) in sliced.chunks_exact(2).enumerate() {
+            assert!((x[0] - (first + step as f32)).abs() < 1e-5);
+            assert!((x[1] - x[0] - 1.0).abs() < 1e-5);
+        }
+
+        let mask = Augmentations {
+            mask_probability: 1.0,
+            mask_ratio: 1.0,
+            ..defaults.clone()
+        };
+        assert!(values(Augmenter::new(mask, 0).augment(ramp()))
+            .iter()
+            .all(|x| *x == 0.0));
+
+        let jitter = Augmentations {
+            jitter_probability: 1.0,
+            ..defaults
+        };
+        let jittered = values(Augmenter::new(jitter.clone(), 7).augment(ramp()));
+        assert_ne!(jittered, values(ramp()));
+        // The same seed gives the same noise
+        assert_eq!(
+            jittered,
+            values(Augmenter::new(jitter.clone(), 7).augment(ramp()))
+        );
+
+        // Whatever thread batches it, and whatever was batched before it
+        let augmenter = Augmenter::new(jitter, 7);
+        let [a, b] = std::thread::scope(|scope| {
+            [0, 1]
+                .map(|_| scope.spawn(|| values(augmenter.augment(ramp()))))
+                .map(|handle| handle.join().unwrap())
+        });
+        augmenter.augment(ramp().add_scalar(1.0));
+        assert_eq!(a, jittered);
+        assert_eq!(b, jittered);
+        assert_eq!(values(augmenter.augment(ramp())), jittered);
+    }
+}
@@ -0,0 +1,333 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex, MutexGuard, RwLock, TryLockError,
+};
+
+const NONE: usize = usize::MAX;
+
+/// A doubly linked list over block indices, ordered from most to least recently used.
+///
+/// The links are stored in arrays indexed by block, so moving a block to the front and removing
+/// the least recently used block are both O(1).
+struct Lru {
+    prev: Box<[usize]>,
+    next: Box<[usize]>,
+    linked: Box<[bool]>,
+    head: usize,
+    tail: usize,
+    len: usize,
+    memory_usage: usize,
+}
+
+impl Lru {
+    fn new(block_count: usize) -> Self {
+        Self {
+            prev: vec![NONE; block_count].into_boxed_slice(),
+            next: vec![NONE; block_count].into_boxed_slice(),
+            linked: vec![false; block_count].into_boxed_slice(),
+            head: NONE,
+            tail: NONE,
+            len: 0,
+            memory_usage: 0,
+        }
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.prev[index], self.next[index]);
+        if prev == NONE {
+            self.head = next;
+        } else {
+            self.next[prev] = next;
+        }
+        if next == NONE {
+            self.tail = prev;
+        } else {
+            self.prev[next] = prev;
+        }
+        self.prev[index] = NONE;
+        self.next[index] = NONE;
+        self.linked[index] = false;
+        self.len -= 1;
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.next[index] = self.head;
+        if self.head == NONE {
+            self.tail = index;
+        } else {
+            self.prev[self.head] = index;
+        }
+        self.head = index;
+        self.linked[index] = true;
+        self.len += 1;
+    }
+
+    /// Moves `index` to the front if it is resident.
+    fn touch(&mut self, index: usize) {
+        if self.linked[index] && self.head != index {
+            self.unlink(index);
+            self.push_front(index);
+        }
+    }
+
+    fn pop_back(&mut self) -> Option<usize> {
+        let tail = self.tail;
+        if tail == NONE {
+            return None;
+        }
+        self.unlink(tail);
+        Some(tail)
+    }
+}
+
+struct CacheBlock<T> {
+    items: RwLock<Option<Arc<[T]>>>,
+    /// Held while the block is read from disk, so that concurrent misses on the same block only
+    /// load it once
+    loading: Mutex<()>,
+    /// Estimated resident size of the block in bytes. Until the block is first loaded this is the
+    /// estimate it was created with.
+    memory_size: AtomicUsize,
+}
+
+/// Counters describing how well the block cache is serving reads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads that found their block in memory
+    pub hits: u64,
+    /// Reads that had to load their block themselves
+    pub misses: u64,
+    /// Reads that had to wait for another thread, such as the prefetcher, to finish loading their
+    /// block
+    pub stalls: u64,
+    /// Blocks loaded ahead of time by prefetching
+    pub prefetched: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stalls: AtomicU64,
+    prefetched: AtomicU64,
+}
+
+/// A cache of blocks kept under a memory budget in bytes.
+///
+/// Blocks are loaded without holding the global lock, so a cold load only blocks other accesses to
+/// the same block. The global lock only guards the recency list and the memory accounting.
+pub(crate) struct BlockCache<T> {
+    blocks: Box<[CacheBlock<T>]>,
+    lru: Mutex<Lru>,
+    max_memory_usage: usize,
+    counters: Counters,
+}
+
+impl<T> BlockCache<T> {
+    /// Creates a cache over blocks with the given estimated sizes in bytes.
+    pub(crate) fn new(
+        block_memory_sizes: impl IntoIterator<Item = usize>,
+        max_memory_usage: usize,
+    ) -> Self {
+        let blocks: Box<[_]> = block_memory_sizes
+            .into_iter()
+            .map(|memory_size| CacheBlock {
+                items: RwLock::new(None),
+                loading: Mutex::new(()),
+                memory_size: AtomicUsize::new(memory_size),
+            })
+            .collect();
+        Self {
+            lru: Mutex::new(Lru::new(blocks.len())),
+            blocks,
+            max_memory_usage,
+            counters: Counters::default(),
+        }
+    }
+
+    pub(crate) fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub(crate) fn memory_limit(&self) -> usize {
+        self.max_memory_usage
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.lru.lock().unwrap().memory_usage
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            stalls: self.counters.stalls.load(Ordering::Relaxed),
+            prefetched: self.counters.prefetched.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn is_resident(&self, index: usize) -> bool {
+        self.blocks[index].items.read().unwrap().is_some()
+    }
+
+    fn resident(&self, index: usize) -> Option<Arc<[T]>> {
+        let items = self.blocks[index].items.read().unwrap().clone();
+        if items.is_some() {
+            self.lru.lock().unwrap().touch(index);
+        }
+        items
+    }
+
+    /// Returns block `index`, calling `load` to read it and its resident size in bytes if it is
+    /// not in memory.
+    pub(crate) fn get_or_load(
+        &self,
+        index: usize,
+        load: impl FnOnce() -> (Box<[T]>, usize),
+    ) -> Arc<[T]> {
+        if let Some(items) = self.resident(index) {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return items;
+        }
+
+        let block = &self.blocks[index];
+        let loading = match block.loading.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                let guard = block.loading.lock().unwrap();
+                if let Some(items) = self.resident(index) {
+                    self.counters.stalls.fetch_add(1, Ordering::Relaxed);
+                    return items;
+                }
+                guard
+            }
+            Err(TryLockError::Poisoned(e)) => panic!("{e}"),
+        };
+        if let Some(items) = self.resident(index) {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return items;
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        self.load(index, loading, load)
+    }
+
+    /// Loads block `index` ahead of time, unless it is already in memory or being loaded.
+    pub(crate) fn prefetch(&self, index: usize, load: impl FnOnce() -> (Box<[T]>, usize)) {
+        let Ok(loading) = self.blocks[index].loading.try_lock() else {
+            return;
+        };
+        if self.is_resident(index) {
+            return;
+        }
+        self.counters.prefetched.fetch_add(1, Ordering::Relaxed);
+        self.load(index, loading, load);
+    }
+
+    fn load(
+        &self,
+        index: usize,
+        _loading: MutexGuard<'_, ()>,
+        load: impl FnOnce() -> (Box<[T]>, usize),
+    ) -> Arc<[T]> {
+        let block = &self.blocks[index];
+        self.evict(block.memory_size.load(Ordering::Relaxed), 0);
+
+        let (items, memory_size) = load();
+        let items: Arc<[T]> = items.into();
+        block.memory_size.store(memory_size, Ordering::Relaxed);
+        *block.items.write().unwrap() = Some(items.clone());
+
+        {
+            let mut lru = self.lru.lock().unwrap();
+            lru.push_front(index);
+            lru.memory_usage += memory_size;
+        }
+        self.evict(0, 1);
+
+        items
+    }
+
+    /// Evicts the least recently used blocks until `incoming` more bytes fit in the budget, or
+    /// only `keep` blocks remain.
+    fn evict(&self, incoming: usize, keep: usize) {
+        let mut victims = vec![];
+        {
+            let mut lru = self.lru.lock().unwrap();
+            while lru.len > keep && lru.memory_usage + incoming > self.max_memory_usage {
+                let victim = lru.pop_back().unwrap();
+                lru.memory_usage -= self.blocks[victim].memory_size.load(Ordering::Relaxed);
+                victims.push(victim);
+            }
+        }
+        // Readers that already hold the block keep their Arc, so clearing it outside the lock
+        // is safe
+        for victim in victims {
+            *self.blocks[victim].items.write().unwrap() = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::{BlockCache, CacheStats, Lru};
+
+    #[test]
+    fn lru_order() {
+        let mut lru = Lru::new(4);
+        lru.push_front(0);
+        lru.push_front(1);
+        lru.push_front(2);
+        lru.touch(0);
+        lru.touch(3);
+        assert_eq!(lru.pop_back(), Some(1));
+        assert_eq!(lru.pop_back(), Some(2));
+        assert_eq!(lru.pop_back(), Some(0));
+        assert_eq!(lru.pop_back(), None);
+        assert_eq!(lru.len, 0);
+    }
+
+    #[test]
+    fn concurrent_loads() {
+        let cache = BlockCache::<usize>::new([10; 8], 40);
+        let loads = AtomicUsize::new(0);
+        rayon::scope(|s| {
+            for i in 0..64 {
+                let cache = &cache;
+                let loads = &loads;
+                s.spawn(move |_| {
+                    let block = cache.get_or_load(i % 8, || {
+                        loads.fetch_add(1, Ordering::Relaxed);
+                        (vec![i % 8; 4].into_boxed_slice(), 10)
+                    });
+                    assert_eq!(block[0], i % 8);
+                });
+            }
+        });
+        assert!(cache.memory_usage() <= cache.memory_limit());
+        assert!(loads.load(Ordering::Relaxed) >= 8);
+    }
+
+    #[test]
+    fn prefetch_stats() {
+        let cache = BlockCache::<usize>::new([10; 4], 40);
+        cache.prefetch(0, || (vec![0; 4].into_boxed_slice(), 10));
+        cache.prefetch(0, || unreachable!());
+        assert_eq!(cache.get_or_load(0, || unreachable!())[0], 0);
+        assert_eq!(
+            cache.get_or_load(1, || (vec![1; 4].into_boxed_slice(), 10))[0],
+            1
+        );
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                stalls: 0,
+                prefetched: 1,
+            }
+        );
+    }
+}
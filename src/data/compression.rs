@@ -0,0 +1,152 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::DatasetError;
+
+/// How the items of each `N.slice` file are compressed.
+///
+/// `Lz4` requires the `lz4` feature and `Zstd` requires the `zstd` feature. Datasets written with
+/// a codec that was not compiled in can not be opened.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd {
+        level: i32,
+    },
+}
+
+impl Compression {
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd { .. } => cfg!(feature = "zstd"),
+        }
+    }
+
+    pub(crate) fn check_supported(self) -> Result<(), DatasetError> {
+        if self.is_supported() {
+            Ok(())
+        } else {
+            Err(DatasetError::UnsupportedCompression(self))
+        }
+    }
+}
+
+pub(crate) fn write_block<T: Serialize>(
+    path: &Path,
+    items: &[T],
+    compression: Compression,
+) -> Result<(), DatasetError> {
+    compression.check_supported()?;
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match compression {
+        Compression::None => bincode::serialize_into(&mut writer, items)?,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut writer);
+            bincode::serialize_into(&mut encoder, items)?;
+            encoder.finish().map_err(|e| DatasetError::Io(e.into()))?;
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { level } => {
+            let mut encoder = zstd::stream::Encoder::new(&mut writer, level)?;
+            bincode::serialize_into(&mut encoder, items)?;
+            encoder.finish()?;
+        }
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Counts the bytes that pass through it after decompression.
+struct CountingReader<R> {
+    inner: R,
+    count: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read;
+        Ok(read)
+    }
+}
+
+/// Reads the block at `path`, also returning its size after decompression.
+pub(crate) fn read_block<T: DeserializeOwned>(
+    path: &Path,
+    compression: Compression,
+) -> Result<(Box<[T]>, usize), DatasetError> {
+    compression.check_supported()?;
+    let reader = BufReader::new(File::open(path)?);
+
+    fn read_counted<T: DeserializeOwned>(
+        reader: impl Read,
+    ) -> Result<(Box<[T]>, usize), DatasetError> {
+        let mut reader = CountingReader {
+            inner: reader,
+            count: 0,
+        };
+        let items = bincode::deserialize_from(&mut reader)?;
+        Ok((items, reader.count))
+    }
+
+    match compression {
+        Compression::None => read_counted(reader),
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => read_counted(lz4_flex::frame::FrameDecoder::new(reader)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd { .. } => read_counted(zstd::stream::Decoder::new(reader)?),
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::{read_block, write_block, Compression};
+
+    fn round_trip(compression: Compression) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("0.slice");
+        let items: Vec<(Vec<f32>, f32)> = (0..100).map(|i| (vec![i as f32; 8], i as f32)).collect();
+        write_block(&path, &items, compression).unwrap();
+        let (read, decoded_bytes) = read_block(&path, compression).unwrap();
+        assert_eq!(read, items.into_boxed_slice());
+        assert_eq!(
+            decoded_bytes as u64,
+            bincode::serialized_size(&read).unwrap()
+        );
+    }
+
+    #[test]
+    fn round_trip_uncompressed() {
+        round_trip(Compression::None);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn round_trip_lz4() {
+        round_trip(Compression::Lz4);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn round_trip_zstd() {
+        round_trip(Compression::Zstd { level: 3 });
+    }
+}
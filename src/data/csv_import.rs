@@ -0,0 +1,267 @@
+use std::{collections::VecDeque, path::Path};
+
+use csv::{ReaderBuilder, StringRecord};
+
+use super::{BlockWriter, Compression, DatasetError, RegressionItem};
+
+/// A column of a CSV file, either by its header or by its position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvColumn {
+    Name(String),
+    Index(usize),
+}
+
+impl From<&str> for CsvColumn {
+    fn from(name: &str) -> Self {
+        Self::Name(name.into())
+    }
+}
+
+impl From<usize> for CsvColumn {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+impl CsvColumn {
+    fn position(&self, headers: Option<&StringRecord>) -> Result<usize, DatasetError> {
+        match self {
+            CsvColumn::Index(index) => Ok(*index),
+            CsvColumn::Name(name) => headers
+                .and_then(|headers| headers.iter().position(|header| header.trim() == name))
+                .ok_or_else(|| {
+                    DatasetError::Malformed(format!("CSV has no column named `{name}`"))
+                }),
+        }
+    }
+}
+
+/// How to turn the rows of a CSV file into [`RegressionItem`]s.
+///
+/// Each item holds the features of `window` consecutive rows as its inputs, and the targets of the
+/// row `horizon` rows after the last of them. Windows start every `stride` rows.
+#[derive(Debug, Clone)]
+pub struct CsvImportOptions {
+    pub features: Vec<CsvColumn>,
+    pub targets: Vec<CsvColumn>,
+    /// The column holding the sample weight of each row, given to the items that take their
+    /// targets from it. Empty fields leave the item unweighted.
+    pub weight: Option<CsvColumn>,
+    pub has_headers: bool,
+    pub delimiter: u8,
+    pub window: usize,
+    pub stride: usize,
+    pub horizon: usize,
+    pub block_memory_size: usize,
+    pub compression: Compression,
+}
+
+impl CsvImportOptions {
+    /// One item per row, reading a comma separated file with a header row.
+    pub fn new(
+        features: impl IntoIterator<Item = impl Into<CsvColumn>>,
+        targets: impl IntoIterator<Item = impl Into<CsvColumn>>,
+    ) -> Self {
+        Self {
+            features: features.into_iter().map(Into::into).collect(),
+            targets: targets.into_iter().map(Into::into).collect(),
+            weight: None,
+            has_headers: true,
+            delimiter: b',',
+            window: 1,
+            stride: 1,
+            horizon: 0,
+            block_memory_size: 1 << 20,
+            compression: Compression::default(),
+        }
+    }
+
+    pub fn with_window(mut self, window: usize, stride: usize) -> Self {
+        self.window = window;
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_horizon(mut self, horizon: usize) -> Self {
+        self.horizon = horizon;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: impl Into<CsvColumn>) -> Self {
+        self.weight = Some(weight.into());
+        self
+    }
+
+    pub fn with_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_block_memory_size(mut self, block_memory_size: usize) -> Self {
+        self.block_memory_size = block_memory_size;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+}
+
+impl From<csv::Error> for DatasetError {
+    fn from(value: csv::Error) -> Self {
+        match value.into_kind() {
+            csv::ErrorKind::Io(e) => Self::Io(e),
+            kind => Self::Malformed(format!("CSV could not be parsed: {kind:?}")),
+        }
+    }
+}
+
+/// Writes the rows of the CSV file at `csv_path` into a dataset of [`RegressionItem`]s at
+/// `data_path`, replacing any dataset already there. Returns the number of items written.
+///
+/// The file is read row by row, so it does not need to fit in memory. Empty fields are read as
+/// NaN.
+pub fn import_csv(
+    csv_path: &Path,
+    data_path: &Path,
+    options: &CsvImportOptions,
+) -> Result<usize, DatasetError> {
+    assert!(
+        options.window > 0 && options.stride > 0,
+        "CSV windows and strides should be at least one row"
+    );
+    let mut reader = ReaderBuilder::new()
+        .has_headers(options.has_headers)
+        .delimiter(options.delimiter)
+        .from_path(csv_path)?;
+    let headers = if options.has_headers {
+        Some(reader.headers()?.clone())
+    } else {
+        None
+    };
+    let positions = |columns: &[CsvColumn]| {
+        columns
+            .iter()
+            .map(|column| column.position(headers.as_ref()))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let features = positions(&options.features)?;
+    let targets = positions(&options.targets)?;
+    let weight = positions(options.weight.as_slice())?;
+
+    let mut writer =
+        BlockWriter::create(data_path, options.block_memory_size, options.compression)?;
+
+    // The rows of the current window and its horizon, with the index of the first of them
+    let span = options.window + options.horizon;
+    let mut rows: VecDeque<(Vec<f32>, Vec<f32>, Option<f32>)> = VecDeque::with_capacity(span);
+    let mut first_row = 0;
+    let mut record = StringRecord::new();
+    let mut line = usize::from(options.has_headers);
+    while reader.read_record(&mut record)? {
+        line += 1;
+        let parse = |positions: &[usize]| {
+            positions
+                .iter()
+                .map(|position| {
+                    let field = record.get(*position).ok_or_else(|| {
+                        DatasetError::Malformed(format!("CSV line {line} has no column {position}"))
+                    })?;
+                    match field.trim() {
+                        "" => Ok(f32::NAN),
+                        field => field.parse().map_err(|_| {
+                            DatasetError::Malformed(format!(
+                                "CSV line {line} column {position} is not a number: `{field}`"
+                            ))
+                        }),
+                    }
+                })
+                .collect::<Result<Vec<f32>, _>>()
+        };
+        let weight = parse(&weight)?
+            .first()
+            .copied()
+            .filter(|weight| !weight.is_nan());
+        rows.push_back((parse(&features)?, parse(&targets)?, weight));
+        if rows.len() < span {
+            continue;
+        }
+
+        if first_row % options.stride == 0 {
+            writer.push(RegressionItem {
+                inputs: rows
+                    .iter()
+                    .take(options.window)
+                    .flat_map(|(features, _, _)| features)
+                    .copied()
+                    .collect(),
+                input_shape: [options.window, features.len()],
+                targets: rows[span - 1].1.clone(),
+                weight: rows[span - 1].2,
+            })?;
+        }
+        rows.pop_front();
+        first_row += 1;
+    }
+
+    Ok(writer.finish()?.length)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::data::dataset::Dataset;
+    use tempfile::tempdir;
+
+    use super::{import_csv, CsvColumn, CsvImportOptions};
+    use crate::data::{AcademyDataset, RegressionItem};
+
+    #[test]
+    fn csv_import_01() {
+        let dir = tempdir().unwrap();
+        let csv_path = dir.path().join("series.csv");
+        let data_path = dir.path().join("data");
+        let mut csv = "time,a,b,y\n".to_string();
+        for i in 0..20 {
+            csv += &format!("{i},{},{},{}\n", i * 10, -i, i * 100);
+        }
+        csv += "20,,0,2000\n";
+        std::fs::write(&csv_path, csv).unwrap();
+
+        let options = CsvImportOptions::new(["a", "b"], [CsvColumn::Index(3)])
+            .with_window(3, 2)
+            .with_horizon(1)
+            .with_block_memory_size(100);
+        // Windows start at rows 0, 2, .., 16, each with the target of the row after them
+        assert_eq!(import_csv(&csv_path, &data_path, &options).unwrap(), 9);
+        let db = AcademyDataset::<RegressionItem>::new(data_path.clone(), 1 << 20);
+        assert_eq!(db.len(), 9);
+        assert!(db.block_count() > 1);
+        assert_eq!(
+            db.get(1).unwrap(),
+            RegressionItem {
+                inputs: vec![20.0, -2.0, 30.0, -3.0, 40.0, -4.0],
+                input_shape: [3, 2],
+                targets: vec![500.0],
+                weight: None,
+            }
+        );
+
+        let options = CsvImportOptions::new(["a"], ["y"]).with_weight("time");
+        assert_eq!(import_csv(&csv_path, &data_path, &options).unwrap(), 21);
+        let db = AcademyDataset::<RegressionItem>::new(data_path, 1 << 20);
+        assert!(db.get(20).unwrap().inputs[0].is_nan());
+        assert_eq!(db.get(3).unwrap().weight, Some(3.0));
+        assert!(import_csv(
+            &csv_path,
+            &dir.path().join("x"),
+            &CsvImportOptions::new(["c"], ["y"])
+        )
+        .is_err());
+    }
+}
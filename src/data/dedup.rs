@@ -0,0 +1,254 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    format::AcademyDatasetConfig,
+    tools::{open_blocks, read_nth_block, rewrite},
+    BlockWriter, DatasetError, DatasetItem,
+};
+
+/// The most example pairs kept for each kind of duplicate
+const MAX_EXAMPLES: usize = 10;
+
+const FNV128_OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV128_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// A 128 bit FNV-1a hash of the serialized item, wide enough that distinct items practically
+/// never collide.
+fn item_hash<T: Serialize>(item: &T) -> Result<u128, DatasetError> {
+    let mut hash = FNV128_OFFSET;
+    for byte in bincode::serialize(item)? {
+        hash ^= byte as u128;
+        hash = hash.wrapping_mul(FNV128_PRIME);
+    }
+    Ok(hash)
+}
+
+/// The hashes of every item of the dataset at `data_path`, in order, hashing its blocks in
+/// parallel.
+fn dataset_hashes<T>(data_path: &Path) -> Result<Vec<u128>, DatasetError>
+where
+    T: Serialize + DeserializeOwned + DatasetItem,
+{
+    let config = open_blocks::<T>(data_path)?;
+    let blocks = (0..config.blocks.len())
+        .into_par_iter()
+        .map(|block| {
+            read_nth_block::<T>(data_path, &config, block)?
+                .iter()
+                .map(item_hash)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(blocks.concat())
+}
+
+/// Items that repeat an earlier item.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Duplicates {
+    pub count: usize,
+    /// Up to 10 pairs of the index of the earlier item and the index of its repeat
+    pub examples: Vec<(usize, usize)>,
+}
+
+impl Duplicates {
+    fn add(&mut self, original: usize, duplicate: usize) {
+        self.count += 1;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push((original, duplicate));
+        }
+    }
+}
+
+/// The exact duplicates found by [`find_duplicates`], by the position of each dataset in the
+/// paths it was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateReport {
+    pub lengths: Vec<usize>,
+    /// For each dataset, its items that repeat one of its earlier items
+    pub within: Vec<Duplicates>,
+    /// For each pair of datasets `(a, b)` with `a < b`, the items of `b` that are also in `a`.
+    /// Pairs without any are left out.
+    pub across: Vec<((usize, usize), Duplicates)>,
+}
+
+impl DuplicateReport {
+    pub fn has_duplicates(&self) -> bool {
+        self.within.iter().any(|duplicates| duplicates.count > 0) || !self.across.is_empty()
+    }
+}
+
+/// Hashes every item of the datasets at `data_paths` and reports the items that are exact
+/// duplicates of each other, such as validation items that leaked from the training dataset.
+///
+/// The hash of every distinct item is kept in memory.
+pub fn find_duplicates<T>(data_paths: &[&Path]) -> Result<DuplicateReport, DatasetError>
+where
+    T: Serialize + DeserializeOwned + DatasetItem,
+{
+    // The first index of each hash in each dataset it was seen in
+    let mut seen: HashMap<u128, Vec<(usize, usize)>> = HashMap::new();
+    let mut lengths = vec![];
+    let mut within = vec![];
+    let mut across: HashMap<(usize, usize), Duplicates> = HashMap::new();
+
+    for (dataset, data_path) in data_paths.iter().enumerate() {
+        let hashes = dataset_hashes::<T>(data_path)?;
+        let mut duplicates = Duplicates::default();
+        for (index, hash) in hashes.iter().enumerate() {
+            let firsts = seen.entry(*hash).or_default();
+            for (other, first) in firsts.iter() {
+                if *other == dataset {
+                    duplicates.add(*first, index);
+                } else {
+                    across
+                        .entry((*other, dataset))
+                        .or_default()
+                        .add(*first, index);
+                }
+            }
+            if firsts.last().map(|(other, _)| *other) != Some(dataset) {
+                firsts.push((dataset, index));
+            }
+        }
+        if duplicates.count > 0 {
+            log::warn!(
+                "Dataset at {} has {} duplicate items",
+                data_path.display(),
+                duplicates.count
+            );
+        }
+        lengths.push(hashes.len());
+        within.push(duplicates);
+    }
+
+    let mut across: Vec<_> = across.into_iter().collect();
+    across.sort_unstable_by_key(|(pair, _)| *pair);
+    for ((a, b), duplicates) in &across {
+        log::warn!(
+            "{} items of the dataset at {} are also in the dataset at {}",
+            duplicates.count,
+            data_paths[*b].display(),
+            data_paths[*a].display()
+        );
+    }
+    Ok(DuplicateReport {
+        lengths,
+        within,
+        across,
+    })
+}
+
+/// Writes a copy of the dataset at `data_path` into `output_path` without the items that repeat
+/// one of its earlier items or appear in any of the datasets at `reference_paths`.
+/// `output_path` may be `data_path` itself. Returns the number of items removed.
+///
+/// Files such as saved splits are not carried over, as the indices in them no longer refer to the
+/// same items.
+pub fn deduplicate_dataset<T>(
+    data_path: &Path,
+    output_path: &Path,
+    reference_paths: &[&Path],
+) -> Result<usize, DatasetError>
+where
+    T: Serialize + DeserializeOwned + DatasetItem,
+{
+    let mut seen = HashSet::new();
+    for reference_path in reference_paths {
+        seen.extend(dataset_hashes::<T>(reference_path)?);
+    }
+    let config = open_blocks::<T>(data_path)?;
+    let mut removed = 0;
+    rewrite(data_path, output_path, false, |output_path| {
+        let mut writer = BlockWriter::new(
+            output_path.into(),
+            AcademyDatasetConfig {
+                blocks: vec![],
+                ..config.clone()
+            },
+        );
+        for block in 0..config.blocks.len() {
+            for item in read_nth_block::<T>(data_path, &config, block)?.into_vec() {
+                if seen.insert(item_hash(&item)?) {
+                    writer.push(item)?;
+                } else {
+                    removed += 1;
+                }
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    })?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::data::dataset::Dataset;
+    use tempfile::tempdir;
+
+    use super::{deduplicate_dataset, find_duplicates};
+    use crate::data::{create_dataset, AcademyDataset, DataGenerator, IndexedDataGen};
+
+    /// Repeats every value 0..period
+    struct ModGen(usize);
+
+    impl IndexedDataGen for ModGen {
+        type Output = u32;
+
+        fn gen_at(&self, index: usize, seed: u64) -> Self::Output {
+            (index % self.0) as u32 + seed as u32
+        }
+    }
+
+    #[test]
+    fn duplicates_01() {
+        let dir = tempdir().unwrap();
+        let train_path = dir.path().join("train");
+        let valid_path = dir.path().join("valid");
+        // 0..100 twice, and 90..110
+        create_dataset(
+            200,
+            train_path.clone(),
+            100,
+            DataGenerator::Indexed(&ModGen(100), 0),
+        );
+        create_dataset(
+            20,
+            valid_path.clone(),
+            100,
+            DataGenerator::Indexed(&ModGen(20), 90),
+        );
+
+        let report = find_duplicates::<u32>(&[&train_path, &valid_path]).unwrap();
+        assert!(report.has_duplicates());
+        assert_eq!(report.lengths, [200, 20]);
+        assert_eq!(report.within[0].count, 100);
+        assert_eq!(report.within[0].examples[0], (0, 100));
+        assert_eq!(report.within[1].count, 0);
+        assert_eq!(report.across.len(), 1);
+        let ((a, b), leaked) = &report.across[0];
+        assert_eq!((*a, *b, leaked.count), (0, 1, 10));
+        assert_eq!(leaked.examples[0], (90, 0));
+
+        let removed = deduplicate_dataset::<u32>(&valid_path, &valid_path, &[&train_path]).unwrap();
+        assert_eq!(removed, 10);
+        let valid = AcademyDataset::<u32>::new(valid_path.clone(), 1000);
+        assert_eq!(
+            (0..valid.len())
+                .map(|i| valid.get(i).unwrap())
+                .collect::<Vec<_>>(),
+            (100..110).collect::<Vec<_>>()
+        );
+        assert!(!find_duplicates::<u32>(&[&train_path, &valid_path])
+            .unwrap()
+            .across
+            .iter()
+            .any(|(_, duplicates)| duplicates.count > 0));
+    }
+}
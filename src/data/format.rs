@@ -0,0 +1,427 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Compression;
+
+pub(crate) const MAGIC: [u8; 8] = *b"MACADEMY";
+pub(crate) const FORMAT_VERSION: u32 = 4;
+pub(crate) const CONFIG_FILE: &str = "config.dat";
+
+/// Item types that can be stored in an [`AcademyDataset`](super::AcademyDataset).
+///
+/// `schema_hash` fingerprints the serialized layout of the type and is recorded in `config.dat`.
+/// It should change whenever the fields of the type, their order or their types change.
+/// [`combine_schema`] can be used to build it from the hashes of the fields.
+pub trait DatasetItem {
+    fn schema_hash() -> u64;
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub(crate) fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+pub fn combine_schema(name: &str, fields: &[u64]) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET, name.as_bytes());
+    for field in fields {
+        hash = fnv1a(hash, &field.to_le_bytes());
+    }
+    hash
+}
+
+macro_rules! impl_primitive_item {
+    ($($ty:ty),*) => {
+        $(
+            impl DatasetItem for $ty {
+                fn schema_hash() -> u64 {
+                    combine_schema(stringify!($ty), &[])
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive_item!(
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    bool,
+    char,
+    String,
+    ()
+);
+
+impl<T: DatasetItem> DatasetItem for Vec<T> {
+    fn schema_hash() -> u64 {
+        combine_schema("Seq", &[T::schema_hash()])
+    }
+}
+
+impl<T: DatasetItem> DatasetItem for Box<[T]> {
+    fn schema_hash() -> u64 {
+        combine_schema("Seq", &[T::schema_hash()])
+    }
+}
+
+impl<T: DatasetItem> DatasetItem for Option<T> {
+    fn schema_hash() -> u64 {
+        combine_schema("Option", &[T::schema_hash()])
+    }
+}
+
+impl<T: DatasetItem, const N: usize> DatasetItem for [T; N] {
+    fn schema_hash() -> u64 {
+        combine_schema("Array", &[T::schema_hash(), N as u64])
+    }
+}
+
+macro_rules! impl_tuple_item {
+    ($($name:ident),+) => {
+        impl<$($name: DatasetItem),+> DatasetItem for ($($name,)+) {
+            fn schema_hash() -> u64 {
+                combine_schema("Tuple", &[$($name::schema_hash()),+])
+            }
+        }
+    };
+}
+
+impl_tuple_item!(A);
+impl_tuple_item!(A, B);
+impl_tuple_item!(A, B, C);
+impl_tuple_item!(A, B, C, D);
+impl_tuple_item!(A, B, C, D, E);
+impl_tuple_item!(A, B, C, D, E, F);
+
+#[derive(Debug)]
+pub enum DatasetError {
+    Io(std::io::Error),
+    Corrupt(bincode::Error),
+    Malformed(String),
+    UnsupportedVersion(u32),
+    UnsupportedCompression(Compression),
+    StaleGenerator {
+        expected: Option<u64>,
+        found: Option<u64>,
+    },
+    TypeMismatch {
+        expected_type: String,
+        expected_schema: u64,
+        found_type: String,
+        found_schema: u64,
+    },
+}
+
+impl Display for DatasetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatasetError::Io(e) => write!(f, "dataset could not be read: {e}"),
+            DatasetError::Corrupt(e) => write!(f, "dataset is corrupt: {e}"),
+            DatasetError::Malformed(reason) => write!(f, "dataset is malformed: {reason}"),
+            DatasetError::UnsupportedVersion(version) => write!(
+                f,
+                "dataset format version {version} is newer than the supported version {FORMAT_VERSION}"
+            ),
+            DatasetError::UnsupportedCompression(compression) => write!(
+                f,
+                "dataset is compressed with {compression:?}, but support for it was not compiled in"
+            ),
+            DatasetError::StaleGenerator { expected, found } => write!(
+                f,
+                "dataset was written by a generator with fingerprint {found:016x?}, but the generator has fingerprint {expected:016x?}"
+            ),
+            DatasetError::TypeMismatch {
+                expected_type,
+                expected_schema,
+                found_type,
+                found_schema,
+            } => write!(
+                f,
+                "dataset holds items of type `{found_type}` (schema {found_schema:016x}) but was opened as `{expected_type}` (schema {expected_schema:016x})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+impl From<std::io::Error> for DatasetError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<bincode::Error> for DatasetError {
+    fn from(value: bincode::Error) -> Self {
+        Self::Corrupt(value)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DatasetHeader {
+    magic: [u8; 8],
+    pub(crate) version: u32,
+    pub(crate) type_name: String,
+    pub(crate) schema_hash: u64,
+}
+
+impl DatasetHeader {
+    pub(crate) fn of<T: DatasetItem>() -> Self {
+        Self {
+            magic: MAGIC,
+            version: FORMAT_VERSION,
+            type_name: std::any::type_name::<T>().to_string(),
+            schema_hash: T::schema_hash(),
+        }
+    }
+
+    /// Checks that this dataset holds items of type `T`.
+    ///
+    /// Only the schema hash has to match, since type names are not stable across compiler
+    /// versions or module moves.
+    pub(crate) fn check<T: DatasetItem>(&self) -> Result<(), DatasetError> {
+        let expected = Self::of::<T>();
+        if self.schema_hash != expected.schema_hash {
+            return Err(DatasetError::TypeMismatch {
+                expected_type: expected.type_name,
+                expected_schema: expected.schema_hash,
+                found_type: self.type_name.clone(),
+                found_schema: self.schema_hash,
+            });
+        }
+        if self.type_name != expected.type_name {
+            log::warn!(
+                "Dataset items were written as `{}` but are being read as `{}`",
+                self.type_name,
+                expected.type_name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Where a block sits in the dataset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockInfo {
+    /// The index of the first item in the block
+    pub(crate) first_item: usize,
+    pub(crate) items: usize,
+    /// The offset of the block in the serialized items of the whole dataset
+    pub(crate) byte_offset: u64,
+    /// The serialized size of the block before compression. 0 if it is unknown, which is the case
+    /// for datasets written before version 4.
+    pub(crate) bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct AcademyDatasetConfig {
+    pub(crate) block_memory_size: usize,
+    pub(crate) length: usize,
+    pub(crate) compression: Compression,
+    /// The fingerprint of the generator that wrote the dataset
+    pub(crate) fingerprint: Option<u64>,
+    pub(crate) blocks: Vec<BlockInfo>,
+}
+
+impl AcademyDatasetConfig {
+    /// Blocks of `block_size` items, with only the last one allowed to be smaller, as all
+    /// datasets written before version 4 were laid out.
+    fn uniform_blocks(block_count: usize, block_size: usize, length: usize) -> Vec<BlockInfo> {
+        (0..block_count)
+            .map(|block| {
+                let first_item = (block * block_size).min(length);
+                BlockInfo {
+                    first_item,
+                    items: block_size.min(length - first_item),
+                    byte_offset: 0,
+                    bytes: 0,
+                }
+            })
+            .collect()
+    }
+
+    /// Recomputes the item and byte offsets of every block from the sizes of the blocks before it.
+    pub(crate) fn fix_offsets(&mut self) {
+        let (mut first_item, mut byte_offset) = (0, 0);
+        for block in &mut self.blocks {
+            block.first_item = first_item;
+            block.byte_offset = byte_offset;
+            first_item += block.items;
+            byte_offset += block.bytes;
+        }
+    }
+}
+
+/// Configs of older format versions, which are converted into the current one when read.
+mod legacy {
+    use serde::Deserialize;
+
+    use super::{AcademyDatasetConfig, Compression};
+
+    /// Versions 0 and 1
+    #[derive(Deserialize)]
+    pub(super) struct ConfigV1 {
+        block_memory_size: usize,
+        block_count: usize,
+        block_size: usize,
+        length: usize,
+    }
+
+    impl From<ConfigV1> for AcademyDatasetConfig {
+        fn from(value: ConfigV1) -> Self {
+            Self {
+                block_memory_size: value.block_memory_size,
+                length: value.length,
+                compression: Compression::None,
+                fingerprint: None,
+                blocks: Self::uniform_blocks(value.block_count, value.block_size, value.length),
+            }
+        }
+    }
+
+    /// Version 2
+    #[derive(Deserialize)]
+    pub(super) struct ConfigV2 {
+        block_memory_size: usize,
+        block_count: usize,
+        block_size: usize,
+        length: usize,
+        compression: Compression,
+    }
+
+    impl From<ConfigV2> for AcademyDatasetConfig {
+        fn from(value: ConfigV2) -> Self {
+            Self {
+                block_memory_size: value.block_memory_size,
+                length: value.length,
+                compression: value.compression,
+                fingerprint: None,
+                blocks: Self::uniform_blocks(value.block_count, value.block_size, value.length),
+            }
+        }
+    }
+
+    /// Version 3
+    #[derive(Deserialize)]
+    pub(super) struct ConfigV3 {
+        block_memory_size: usize,
+        block_count: usize,
+        block_size: usize,
+        length: usize,
+        compression: Compression,
+        fingerprint: Option<u64>,
+    }
+
+    impl From<ConfigV3> for AcademyDatasetConfig {
+        fn from(value: ConfigV3) -> Self {
+            Self {
+                block_memory_size: value.block_memory_size,
+                length: value.length,
+                compression: value.compression,
+                fingerprint: value.fingerprint,
+                blocks: Self::uniform_blocks(value.block_count, value.block_size, value.length),
+            }
+        }
+    }
+}
+
+/// A `config.dat` as found on disk.
+pub(crate) struct StoredConfig {
+    /// `None` if the dataset was written before headers existed, in which case the item type is
+    /// unknown.
+    pub(crate) header: Option<DatasetHeader>,
+    pub(crate) config: AcademyDatasetConfig,
+}
+
+impl StoredConfig {
+    pub(crate) fn is_current(&self) -> bool {
+        self.header
+            .as_ref()
+            .map(|header| header.version == FORMAT_VERSION)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn check<T: DatasetItem>(&self) -> Result<(), DatasetError> {
+        match &self.header {
+            Some(header) => header.check::<T>(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn read_config(data_path: &Path) -> Result<StoredConfig, DatasetError> {
+    let mut bytes = vec![];
+    File::open(data_path.join(CONFIG_FILE))?.read_to_end(&mut bytes)?;
+
+    if !bytes.starts_with(&MAGIC) {
+        // Version 0: a bare config with no header
+        return Ok(StoredConfig {
+            header: None,
+            config: bincode::deserialize::<legacy::ConfigV1>(&bytes)?.into(),
+        });
+    }
+
+    let mut reader = Cursor::new(bytes);
+    let header: DatasetHeader = bincode::deserialize_from(&mut reader)?;
+    let config = match header.version {
+        1 => bincode::deserialize_from::<_, legacy::ConfigV1>(&mut reader)?.into(),
+        2 => bincode::deserialize_from::<_, legacy::ConfigV2>(&mut reader)?.into(),
+        3 => bincode::deserialize_from::<_, legacy::ConfigV3>(&mut reader)?.into(),
+        4 => bincode::deserialize_from(&mut reader)?,
+        version => return Err(DatasetError::UnsupportedVersion(version)),
+    };
+
+    Ok(StoredConfig {
+        header: Some(header),
+        config,
+    })
+}
+
+pub(crate) fn write_config<T: DatasetItem>(
+    data_path: &Path,
+    config: &AcademyDatasetConfig,
+) -> Result<(), DatasetError> {
+    let mut bytes = bincode::serialize(&DatasetHeader::of::<T>())?;
+    bytes.extend(bincode::serialize(config)?);
+    std::fs::write(data_path.join(CONFIG_FILE), bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine_schema, DatasetItem};
+
+    #[test]
+    fn schema_hashes_differ() {
+        assert_ne!(u8::schema_hash(), f32::schema_hash());
+        assert_ne!(
+            <(Vec<f32>, f32)>::schema_hash(),
+            <(f32, Vec<f32>)>::schema_hash()
+        );
+        assert_ne!(<Vec<f32>>::schema_hash(), <Option<f32>>::schema_hash());
+        assert_eq!(<Vec<u8>>::schema_hash(), <Box<[u8]>>::schema_hash());
+        assert_eq!(combine_schema("a", &[1, 2]), combine_schema("a", &[1, 2]));
+    }
+}
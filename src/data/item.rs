@@ -0,0 +1,54 @@
+use burn::tensor::{backend::Backend, Data, Shape, Tensor};
+use serde::{Deserialize, Serialize};
+
+use super::{combine_schema, DatasetItem};
+
+/// An input and target pair of `f32`s, as written by the importers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RegressionItem {
+    /// `input_shape[0]` rows of `input_shape[1]` features, row by row
+    pub inputs: Vec<f32>,
+    pub input_shape: [usize; 2],
+    pub targets: Vec<f32>,
+    /// How much the item counts towards the loss relative to other items, 1 if not given
+    pub weight: Option<f32>,
+}
+
+/// Items that may weigh more or less than others in the loss, such as rare regimes or recent
+/// data. The default is an unweighted item. Weights are only used when batching with
+/// [`crate::WeightedRegressionBatcher`].
+pub trait SampleWeight {
+    fn sample_weight(&self) -> Option<f32> {
+        None
+    }
+}
+
+impl SampleWeight for RegressionItem {
+    fn sample_weight(&self) -> Option<f32> {
+        self.weight
+    }
+}
+
+impl DatasetItem for RegressionItem {
+    fn schema_hash() -> u64 {
+        combine_schema(
+            "RegressionItem",
+            &[
+                Vec::<f32>::schema_hash(),
+                <[usize; 2]>::schema_hash(),
+                Vec::<f32>::schema_hash(),
+                Option::<f32>::schema_hash(),
+            ],
+        )
+    }
+}
+
+impl<B: Backend> From<RegressionItem> for (Tensor<B, 2>, Tensor<B, 1>) {
+    fn from(item: RegressionItem) -> Self {
+        let target_len = item.targets.len();
+        (
+            Tensor::from_floats(Data::new(item.inputs, Shape::new(item.input_shape))),
+            Tensor::from_floats(Data::new(item.targets, Shape::new([target_len]))),
+        )
+    }
+}
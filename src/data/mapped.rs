@@ -0,0 +1,309 @@
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    ops::Range,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use burn::{
+    data::dataset::Dataset,
+    tensor::{backend::Backend, Data, Shape, Tensor},
+};
+use memmap2::Mmap;
+
+use super::{DatasetError, SampleWeight};
+
+const MAPPED_MAGIC: [u8; 8] = *b"MACADF32";
+const MAPPED_VERSION: u32 = 1;
+/// Kept a multiple of 4 so that the f32 arrays that follow stay aligned within the page aligned
+/// map
+const HEADER_LEN: usize = 64;
+pub(crate) const RECORDS_FILE: &str = "records.f32";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MappedHeader {
+    input_shape: [usize; 2],
+    target_len: usize,
+    length: usize,
+}
+
+impl MappedHeader {
+    fn input_len(&self) -> usize {
+        self.input_shape[0] * self.input_shape[1]
+    }
+
+    /// The length of the record file, or an error if it is too large to address, which only a
+    /// corrupt header describes.
+    fn file_len(&self) -> Result<usize, DatasetError> {
+        self.input_shape[0]
+            .checked_mul(self.input_shape[1])
+            .and_then(|input_len| input_len.checked_add(self.target_len))
+            .and_then(|record_len| record_len.checked_mul(self.length))
+            .and_then(|values| values.checked_mul(4))
+            .and_then(|bytes| bytes.checked_add(HEADER_LEN))
+            .ok_or_else(|| {
+                DatasetError::Malformed(format!(
+                    "{RECORDS_FILE} has a header describing more records than can be addressed"
+                ))
+            })
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..8].copy_from_slice(&MAPPED_MAGIC);
+        bytes[8..12].copy_from_slice(&MAPPED_VERSION.to_le_bytes());
+        bytes[16..24].copy_from_slice(&(self.input_shape[0] as u64).to_le_bytes());
+        bytes[24..32].copy_from_slice(&(self.input_shape[1] as u64).to_le_bytes());
+        bytes[32..40].copy_from_slice(&(self.target_len as u64).to_le_bytes());
+        bytes[40..48].copy_from_slice(&(self.length as u64).to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DatasetError> {
+        if bytes.len() < HEADER_LEN || bytes[0..8] != MAPPED_MAGIC {
+            return Err(DatasetError::Malformed(format!(
+                "{RECORDS_FILE} does not start with a record header"
+            )));
+        }
+        let read_u64 =
+            |range: Range<usize>| u64::from_le_bytes(bytes[range].try_into().unwrap()) as usize;
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version != MAPPED_VERSION {
+            return Err(DatasetError::UnsupportedVersion(version));
+        }
+        Ok(Self {
+            input_shape: [read_u64(16..24), read_u64(24..32)],
+            target_len: read_u64(32..40),
+            length: read_u64(40..48),
+        })
+    }
+}
+
+/// Writes fixed shape `f32` records into `data_path` in the layout read by [`MappedDataset`].
+///
+/// All inputs are stored contiguously, followed by all targets, so that any range of records can
+/// be read as two slices.
+pub fn create_mapped_dataset<X, Y>(
+    data_path: PathBuf,
+    input_shape: [usize; 2],
+    target_len: usize,
+    items: impl IntoIterator<Item = (X, Y)>,
+) where
+    X: AsRef<[f32]>,
+    Y: AsRef<[f32]>,
+{
+    std::fs::create_dir_all(&data_path).expect("Data path directories should be creatable");
+
+    let mut header = MappedHeader {
+        input_shape,
+        target_len,
+        length: 0,
+    };
+    let mut inputs = BufWriter::new(
+        File::create(data_path.join(RECORDS_FILE)).expect("Record file should be creatable"),
+    );
+    let mut targets =
+        BufWriter::new(tempfile::tempfile().expect("Temporary target file should be creatable"));
+    inputs
+        .write_all(&header.to_bytes())
+        .expect("Record file should be writable");
+
+    for (input, target) in items {
+        let input = input.as_ref();
+        let target = target.as_ref();
+        assert_eq!(
+            input.len(),
+            header.input_len(),
+            "Inputs should match input_shape"
+        );
+        assert_eq!(target.len(), target_len, "Targets should match target_len");
+
+        for x in input {
+            inputs
+                .write_all(&x.to_le_bytes())
+                .expect("Record file should be writable");
+        }
+        for y in target {
+            targets
+                .write_all(&y.to_le_bytes())
+                .expect("Temporary target file should be writable");
+        }
+        header.length += 1;
+    }
+
+    let mut targets = targets
+        .into_inner()
+        .expect("Temporary target file should be writable");
+    targets
+        .rewind()
+        .expect("Temporary target file should be seekable");
+    std::io::copy(&mut targets, &mut inputs).expect("Record file should be writable");
+
+    let mut file = inputs.into_inner().expect("Record file should be writable");
+    file.rewind().expect("Record file should be seekable");
+    file.write_all(&header.to_bytes())
+        .expect("Record file should be writable");
+}
+
+/// A dataset of fixed shape `f32` records that are read straight out of a memory map.
+#[derive(Clone)]
+pub struct MappedDataset {
+    map: Arc<Mmap>,
+    header: MappedHeader,
+}
+
+impl MappedDataset {
+    pub fn new(data_path: PathBuf) -> Self {
+        Self::try_new(data_path.clone())
+            .unwrap_or_else(|e| panic!("Dataset at {} should be valid: {e}", data_path.display()))
+    }
+
+    pub fn try_new(data_path: PathBuf) -> Result<Self, DatasetError> {
+        let file = File::open(data_path.join(RECORDS_FILE))?;
+        // SAFETY: Record files are only written by `create_mapped_dataset`, and are not expected to
+        // be modified while they are mapped
+        let map = unsafe { Mmap::map(&file)? };
+        let header = MappedHeader::from_bytes(&map)?;
+        let file_len = header.file_len()?;
+        if map.len() != file_len {
+            return Err(DatasetError::Malformed(format!(
+                "{RECORDS_FILE} is {} bytes long but its header describes {file_len} bytes",
+                map.len(),
+            )));
+        }
+        Ok(Self {
+            map: Arc::new(map),
+            header,
+        })
+    }
+
+    pub fn input_shape(&self) -> [usize; 2] {
+        self.header.input_shape
+    }
+
+    pub fn target_len(&self) -> usize {
+        self.header.target_len
+    }
+
+    fn floats(&self, start: usize, len: usize) -> &[f32] {
+        let bytes = &self.map[HEADER_LEN + start * 4..HEADER_LEN + (start + len) * 4];
+        // SAFETY: The map is page aligned and HEADER_LEN is a multiple of 4, so `bytes` is
+        // aligned for f32. The floats are little endian, which is the native endianness as this
+        // module is only compiled on little endian targets.
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f32, len) }
+    }
+
+    /// The inputs of the records in `range`, laid out contiguously.
+    pub fn inputs(&self, range: Range<usize>) -> Option<&[f32]> {
+        if range.end > self.header.length || range.start > range.end {
+            return None;
+        }
+        let input_len = self.header.input_len();
+        Some(self.floats(range.start * input_len, range.len() * input_len))
+    }
+
+    /// The targets of the records in `range`, laid out contiguously.
+    pub fn targets(&self, range: Range<usize>) -> Option<&[f32]> {
+        if range.end > self.header.length || range.start > range.end {
+            return None;
+        }
+        let offset = self.header.length * self.header.input_len();
+        let target_len = self.header.target_len;
+        Some(self.floats(offset + range.start * target_len, range.len() * target_len))
+    }
+}
+
+impl Dataset<MappedRecord> for MappedDataset {
+    fn get(&self, index: usize) -> Option<MappedRecord> {
+        (index < self.header.length).then(|| MappedRecord {
+            dataset: self.clone(),
+            index,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.header.length
+    }
+}
+
+/// A handle to a single record of a [`MappedDataset`]. Cloning it does not copy the record.
+#[derive(Clone)]
+pub struct MappedRecord {
+    dataset: MappedDataset,
+    index: usize,
+}
+
+impl MappedRecord {
+    pub fn inputs(&self) -> &[f32] {
+        self.dataset.inputs(self.index..self.index + 1).unwrap()
+    }
+
+    pub fn targets(&self) -> &[f32] {
+        self.dataset.targets(self.index..self.index + 1).unwrap()
+    }
+}
+
+impl Debug for MappedRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedRecord")
+            .field("index", &self.index)
+            .field("inputs", &self.inputs())
+            .field("targets", &self.targets())
+            .finish()
+    }
+}
+
+impl<B: Backend> From<MappedRecord> for (Tensor<B, 2>, Tensor<B, 1>) {
+    fn from(record: MappedRecord) -> Self {
+        let input_shape = record.dataset.input_shape();
+        let target_len = record.dataset.target_len();
+        (
+            Tensor::from_floats(Data::new(record.inputs().to_vec(), Shape::new(input_shape))),
+            Tensor::from_floats(Data::new(
+                record.targets().to_vec(),
+                Shape::new([target_len]),
+            )),
+        )
+    }
+}
+
+impl SampleWeight for MappedRecord {}
+
+#[cfg(test)]
+mod tests {
+    use burn::data::dataset::Dataset;
+    use tempfile::tempdir;
+
+    use super::{create_mapped_dataset, MappedDataset, RECORDS_FILE};
+
+    #[test]
+    fn test_mapped_db_01() {
+        let dir = tempdir().unwrap();
+        create_mapped_dataset(
+            dir.path().into(),
+            [3, 2],
+            1,
+            (0..10).map(|i| (vec![i as f32; 6], [-(i as f32)])),
+        );
+
+        let db = MappedDataset::new(dir.path().into());
+        assert_eq!(db.len(), 10);
+        assert_eq!(db.input_shape(), [3, 2]);
+        let record = db.get(4).unwrap();
+        assert_eq!(record.inputs(), &[4.0; 6]);
+        assert_eq!(record.targets(), &[-4.0]);
+        assert_eq!(db.inputs(2..4).unwrap().len(), 12);
+        assert_eq!(db.targets(2..4).unwrap(), &[-2.0, -3.0]);
+        assert!(db.get(10).is_none());
+        assert!(db.inputs(9..11).is_none());
+
+        // A corrupt length that would overflow the file length
+        let records_path = dir.path().join(RECORDS_FILE);
+        let mut bytes = std::fs::read(&records_path).unwrap();
+        bytes[40..48].copy_from_slice(&(u64::MAX / 8).to_le_bytes());
+        std::fs::write(&records_path, bytes).unwrap();
+        assert!(MappedDataset::try_new(dir.path().into()).is_err());
+    }
+}
@@ -1,97 +1,212 @@
-use std::{collections::VecDeque, fs::File, path::PathBuf, sync::Mutex};
+use std::{
+    fmt::Debug,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use burn::data::dataset::Dataset;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-
-struct CacheBlock<T> {
-    items: Mutex<Box<[T]>>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct AcademyDatasetConfig {
-    block_memory_size: usize,
-    block_count: usize,
-    block_size: usize,
-    length: usize,
-}
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use augment::{Augmentations, Augmenter};
+use cache::BlockCache;
+pub use cache::CacheStats;
+pub use compression::Compression;
+use compression::{read_block, write_block};
+pub use csv_import::{import_csv, CsvColumn, CsvImportOptions};
+pub use dedup::{deduplicate_dataset, find_duplicates, DuplicateReport, Duplicates};
+pub use format::{combine_schema, DatasetError, DatasetItem};
+use format::{fnv1a, read_config, write_config, AcademyDatasetConfig, FNV_OFFSET};
+pub use item::{RegressionItem, SampleWeight};
+#[cfg(target_endian = "little")]
+pub use mapped::{create_mapped_dataset, MappedDataset, MappedRecord};
+pub use normalize::{Normalization, Scaler};
+pub use npy::{export_npy, export_npz, import_npy, import_npz, NpyReader, NpyWriter};
+use progress::ProgressTracker;
+pub use progress::{CreationProgress, ProgressCallback};
+pub use sampler::{BlockShuffleSampler, BlockShuffledDataset, EpochPlan};
+pub use series::{create_series_dataset, WindowConfig, WindowedSeriesDataset};
+pub use split::{DatasetSplit, SplitConfig, SplitKind};
+pub use stats::{DatasetStats, FeatureStats};
+pub(crate) use stream::EpochDataLoader;
+pub use stream::{StreamEpoch, StreamingDataset};
+pub use tools::{reblock_dataset, reshuffle_dataset};
+pub use views::{ConcatDataset, MapDataset, SubsetDataset};
+use writer::BlockWriter;
+
+mod augment;
+mod cache;
+mod compression;
+mod csv_import;
+mod dedup;
+mod format;
+mod item;
+#[cfg(target_endian = "little")]
+mod mapped;
+mod normalize;
+mod npy;
+mod progress;
+mod sampler;
+mod series;
+mod split;
+mod stats;
+mod stream;
+mod tools;
+mod views;
+mod writer;
 
 pub struct AcademyDataset<T> {
-    cache: Box<[CacheBlock<T>]>,
-    last_used: Mutex<VecDeque<usize>>,
+    cache: BlockCache<T>,
     length: usize,
-    block_size: usize,
-    max_cached_blocks: usize,
+    /// The index of the first item of each block
+    block_starts: Box<[usize]>,
     data_path: PathBuf,
+    compression: Compression,
+    config_hash: u64,
 }
 
-impl<T> AcademyDataset<T> {
-    pub fn new(data_path: PathBuf, max_cached_blocks: usize) -> Self {
-        let config: AcademyDatasetConfig = bincode::deserialize_from(
-            File::open(data_path.join("config.dat")).expect("config.dat should be readable"),
-        )
-        .expect("config.dat should be valid");
-
-        Self {
-            cache: (0..config.block_count)
-                .into_iter()
-                .map(|_| CacheBlock {
-                    items: Mutex::new(Box::new([])),
-                })
-                .collect(),
-            last_used: Mutex::new(VecDeque::with_capacity(config.block_count)),
+impl<T: DatasetItem + DeserializeOwned> AcademyDataset<T> {
+    /// Opens the dataset at `data_path`, keeping at most `max_memory_usage` bytes of blocks in
+    /// memory. At least one block is always kept, even if it is larger than the budget.
+    pub fn new(data_path: PathBuf, max_memory_usage: usize) -> Self {
+        Self::try_new(data_path.clone(), max_memory_usage)
+            .unwrap_or_else(|e| panic!("Dataset at {} should be valid: {e}", data_path.display()))
+    }
+
+    /// Opens the dataset at `data_path`, checking that it holds items of type `T`.
+    ///
+    /// Datasets written by older versions are migrated to the current format in place, once their
+    /// first block has been read as `T`.
+    pub fn try_new(data_path: PathBuf, max_memory_usage: usize) -> Result<Self, DatasetError> {
+        let stored = read_config(&data_path)?;
+        stored.check::<T>()?;
+        stored.config.compression.check_supported()?;
+        if !stored.is_current() {
+            // Older datasets may not have recorded their item type, so it is only recorded once
+            // the items are known to be readable as `T`
+            check_first_block::<T>(&data_path, &stored.config)?;
+            if let Err(e) = write_config::<T>(&data_path, &stored.config) {
+                log::warn!(
+                    "Dataset at {} could not be migrated to the current format: {e}",
+                    data_path.display()
+                );
+            }
+        }
+        let config = stored.config;
+        let config_hash = fnv1a(FNV_OFFSET, &bincode::serialize(&config)?);
+
+        // Estimated the same way as load_block does, until the blocks are actually loaded
+        let block_memory_sizes = config.blocks.iter().map(|block| match block.bytes {
+            0 => config.block_memory_size,
+            bytes => bytes as usize + block.items * std::mem::size_of::<T>(),
+        });
+        Ok(Self {
+            cache: BlockCache::new(block_memory_sizes, max_memory_usage),
             length: config.length,
-            block_size: config.block_size,
-            max_cached_blocks,
+            block_starts: config.blocks.iter().map(|block| block.first_item).collect(),
             data_path,
+            compression: config.compression,
+            config_hash,
+        })
+    }
+}
+
+/// Checks that the first block of the dataset at `data_path` holds as many items of type `T` as
+/// `config` says it does.
+fn check_first_block<T: DeserializeOwned>(
+    data_path: &Path,
+    config: &AcademyDatasetConfig,
+) -> Result<(), DatasetError> {
+    let Some(block) = config.blocks.first() else {
+        return Ok(());
+    };
+    let (items, _) = read_block::<T>(&data_path.join("0.slice"), config.compression)?;
+    if items.len() != block.items {
+        return Err(DatasetError::Malformed(format!(
+            "the first block holds {} items but {} were expected",
+            items.len(),
+            block.items
+        )));
+    }
+    Ok(())
+}
+
+impl<T> AcademyDataset<T> {
+    pub fn data_path(&self) -> &Path {
+        &self.data_path
+    }
+
+    /// A hash of the layout of the dataset and the fingerprint of the generator that wrote it, which
+    /// changes whenever the dataset is rewritten differently.
+    pub fn config_hash(&self) -> u64 {
+        self.config_hash
+    }
+
+    /// The most bytes of blocks that will be kept in memory.
+    pub fn memory_limit(&self) -> usize {
+        self.cache.memory_limit()
+    }
+
+    /// The estimated bytes of blocks currently in memory.
+    pub fn memory_usage(&self) -> usize {
+        self.cache.memory_usage()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.cache.block_count()
+    }
+
+    /// The indices of the items stored in `block`.
+    pub fn block_range(&self, block: usize) -> Range<usize> {
+        let start = self.block_starts.get(block).copied().unwrap_or(self.length);
+        let end = self
+            .block_starts
+            .get(block + 1)
+            .copied()
+            .unwrap_or(self.length);
+        start..end
+    }
+
+    /// The block holding the item at `index`, if there is one.
+    pub fn block_of(&self, index: usize) -> Option<usize> {
+        if index >= self.length {
+            return None;
         }
+        Some(self.block_starts.partition_point(|start| *start <= index) - 1)
     }
 }
 
-impl<T: DeserializeOwned + Send + Sync + Clone> Dataset<T> for AcademyDataset<T> {
-    fn get(&self, index: usize) -> Option<T> {
-        let block_index = index / self.block_size;
-        let block = self.cache.get(block_index)?;
-        let mut items = block.items.lock().unwrap();
-        let mut last_used = self.last_used.lock().unwrap();
-
-        if items.is_empty() {
-            if last_used.len() >= self.max_cached_blocks {
-                let last_used_index = last_used.pop_back().unwrap();
-                *self
-                    .cache
-                    .get(last_used_index)
-                    .unwrap()
-                    .items
-                    .lock()
-                    .unwrap() = Box::new([]);
-            }
-            last_used.push_front(block_index);
-            let filename = format!("{block_index}.slice");
-            let slice_path = self.data_path.join(filename);
-
-            *items = bincode::deserialize_from(
-                File::open(slice_path).expect("Database slice should be readable"),
-            )
-            .expect("Database slice should be valid");
-        } else {
-            let mut tmp_indices = Vec::with_capacity(self.max_cached_blocks);
-            loop {
-                let Some(i) = last_used.pop_front() else {
-                    break;
-                };
-                if i == block_index {
-                    break;
-                }
-                tmp_indices.push(i);
-            }
-            for i in tmp_indices.into_iter().rev() {
-                last_used.push_front(i);
-            }
-            last_used.push_front(block_index);
+impl<T: DeserializeOwned> AcademyDataset<T> {
+    fn load_block(&self, block_index: usize) -> (Box<[T]>, usize) {
+        let slice_path = self.data_path.join(format!("{block_index}.slice"));
+        let (items, decoded_bytes) = read_block(&slice_path, self.compression)
+            .expect("Database slice should be readable and valid");
+        // Serialized sizes undercount heap allocations and inline sizes undercount heap data,
+        // so both are counted to stay on the safe side of the budget
+        let memory_size = decoded_bytes + std::mem::size_of_val::<[T]>(&items);
+        (items, memory_size)
+    }
+
+    /// Loads `block` into memory ahead of time, unless it is already resident or being loaded.
+    pub fn prefetch(&self, block: usize) {
+        if block < self.cache.block_count() {
+            self.cache.prefetch(block, || self.load_block(block));
         }
+    }
+}
 
-        items.get(index % self.block_size).cloned()
+impl<T: DeserializeOwned + Send + Sync + Clone> Dataset<T> for AcademyDataset<T> {
+    fn get(&self, index: usize) -> Option<T> {
+        let block_index = self.block_of(index)?;
+        let items = self
+            .cache
+            .get_or_load(block_index, || self.load_block(block_index));
+        items.get(index - self.block_starts[block_index]).cloned()
     }
 
     fn len(&self) -> usize {
@@ -104,6 +219,12 @@ pub trait DataGen: Sync {
 
     fn gen(&self) -> Self::Output;
     fn skip(&mut self, num: usize);
+
+    /// A hash of the parameters that affect the generated items, used to detect datasets written
+    /// by a differently configured generator. `None` if the generator does not report one.
+    fn fingerprint(&self) -> Option<u64> {
+        None
+    }
 }
 
 pub trait MutDataGen {
@@ -111,273 +232,225 @@ pub trait MutDataGen {
 
     fn gen(&mut self) -> Self::Output;
     fn skip(&mut self, num: usize);
+
+    /// See [`DataGen::fingerprint`].
+    fn fingerprint(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A generator whose items are a pure function of their index in the dataset and a seed.
+///
+/// Unlike [`DataGen`], the items it produces do not depend on the order they are generated in,
+/// so datasets generated in parallel are reproducible and resuming does not need to skip items.
+pub trait IndexedDataGen: Sync {
+    type Output;
+
+    fn gen_at(&self, index: usize, seed: u64) -> Self::Output;
+
+    /// See [`DataGen::fingerprint`]. The seed is combined into it when the dataset is created.
+    fn fingerprint(&self) -> Option<u64> {
+        None
+    }
 }
 
 pub enum DataGenerator<'a, T> {
     Immut(&'a mut dyn DataGen<Output = T>),
     Mut(&'a mut dyn MutDataGen<Output = T>),
+    Indexed(&'a dyn IndexedDataGen<Output = T>, u64),
 }
 
-pub fn create_dataset<T: Serialize + Send>(
-    length: usize,
-    data_path: PathBuf,
-    block_memory_size: usize,
-    mut gen: DataGenerator<'_, T>,
-) {
-    std::fs::create_dir_all(&data_path).expect("Data path directories should be creatable");
-
-    let mut init_config = None;
-
-    if let Ok(config_file) = File::open(data_path.join("config.dat")) {
-        if let Ok(config) = bincode::deserialize_from::<_, AcademyDatasetConfig>(config_file) {
-            if config.length == length {
-                init_config = Some(config);
-            } else {
-                std::fs::remove_file(data_path.join("config.dat"))
-                    .expect("Config file should have been deletable");
+impl<T: Send> DataGenerator<'_, T> {
+    /// Generates the items at the indices in `range`. Sequential generators ignore the indices
+    /// and generate their next items.
+    fn gen_range(&mut self, range: Range<usize>) -> Box<[T]> {
+        match self {
+            DataGenerator::Immut(x) => {
+                let x = &**x;
+                range.into_par_iter().map(|_| x.gen()).collect()
+            }
+            DataGenerator::Mut(x) => range.map(|_| x.gen()).collect(),
+            DataGenerator::Indexed(x, seed) => {
+                let (x, seed) = (*x, *seed);
+                range.into_par_iter().map(|i| x.gen_at(i, seed)).collect()
             }
         }
     }
 
-    let mut first_block = vec![];
-    let mut block_size = 0usize;
-    let remaining_block_count;
-    let small_block_count;
-    let block_count;
-
-    if let Some(config) = &init_config {
-        block_size = config.block_size;
-        first_block = Vec::with_capacity(block_size);
-        small_block_count = (length - block_size) % block_size;
-        remaining_block_count = (length - block_size) / block_size;
-        block_count = config.block_count;
-
-        match &mut gen {
-            DataGenerator::Immut(x) => x.skip(block_size),
-            DataGenerator::Mut(x) => x.skip(block_size),
+    fn skip(&mut self, num: usize) {
+        match self {
+            DataGenerator::Immut(x) => x.skip(num),
+            DataGenerator::Mut(x) => x.skip(num),
+            DataGenerator::Indexed(..) => {}
         }
-    } else {
-        for _ in 0..length {
-            first_block.push(match &mut gen {
-                DataGenerator::Immut(x) => x.gen(),
-                DataGenerator::Mut(x) => x.gen(),
-            });
-            block_size += 1;
-            if bincode::serialized_size(&first_block).expect("Type T should be serializable")
-                as usize
-                >= block_memory_size
-            {
-                break;
-            }
+    }
+
+    fn fingerprint(&self) -> Option<u64> {
+        match self {
+            DataGenerator::Immut(x) => x.fingerprint(),
+            DataGenerator::Mut(x) => x.fingerprint(),
+            DataGenerator::Indexed(x, seed) => x
+                .fingerprint()
+                .map(|fingerprint| combine_schema("Indexed", &[fingerprint, *seed])),
         }
-        bincode::serialize_into(
-            File::create(data_path.join("0.slice")).expect("Database slice should be creatable"),
-            &first_block,
-        )
-        .expect("Database slice should be writable, and the type T should be serializable");
+    }
+}
 
-        small_block_count = (length - block_size) % block_size;
-        remaining_block_count = (length - block_size) / block_size;
-        block_count = if small_block_count > 0 {
-            remaining_block_count + 2
-        } else {
-            remaining_block_count + 1
-        };
+/// How often `create_dataset` saves its progress to `config.dat`.
+const CONFIG_WRITE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Default)]
+pub struct DatasetOptions {
+    pub compression: Compression,
+    /// Return [`DatasetError::StaleGenerator`] instead of regenerating the dataset when it was
+    /// written by a generator with a different fingerprint
+    pub strict: bool,
+    /// Called after every block is written or found on disk. Progress is also logged at the
+    /// info level regardless.
+    pub on_progress: Option<ProgressCallback>,
+}
 
-        let config = AcademyDatasetConfig {
-            block_memory_size,
-            block_count,
-            block_size,
-            length,
-        };
+impl Debug for DatasetOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatasetOptions")
+            .field("compression", &self.compression)
+            .field("strict", &self.strict)
+            .field("on_progress", &self.on_progress.is_some())
+            .finish()
+    }
+}
 
-        bincode::serialize_into(
-            File::create(data_path.join("config.dat"))
-                .expect("Database config should be creatable"),
-            &config,
+pub fn create_dataset<T: Serialize + Send + DatasetItem>(
+    length: usize,
+    data_path: PathBuf,
+    block_memory_size: usize,
+    gen: DataGenerator<'_, T>,
+) {
+    create_dataset_with_options(
+        length,
+        data_path.clone(),
+        block_memory_size,
+        gen,
+        DatasetOptions::default(),
+    )
+    .unwrap_or_else(|e| {
+        panic!(
+            "Dataset at {} should be creatable: {e}",
+            data_path.display()
         )
-        .expect("Database config should be writable");
+    })
+}
 
-        if block_size >= length {
-            return;
-        }
-        first_block.clear();
-    }
+pub fn create_dataset_with_options<T: Serialize + Send + DatasetItem>(
+    length: usize,
+    data_path: PathBuf,
+    block_memory_size: usize,
+    mut gen: DataGenerator<'_, T>,
+    options: DatasetOptions,
+) -> Result<(), DatasetError> {
+    let start = Instant::now();
+    let compression = options.compression;
+    compression.check_supported()?;
+    std::fs::create_dir_all(&data_path)?;
+
+    let fingerprint = gen.fingerprint();
+    let mut init_config = None;
 
-    if small_block_count > 0 {
-        let small_block_path = data_path.join(format!("{}.slice", remaining_block_count + 1));
-        if init_config.is_none() || !small_block_path.exists() {
-            for _ in 0..small_block_count {
-                first_block.push(match &mut gen {
-                    DataGenerator::Immut(x) => x.gen(),
-                    DataGenerator::Mut(x) => x.gen(),
-                });
+    if let Ok(stored) = read_config(&data_path) {
+        let fresh = stored.config.fingerprint == fingerprint;
+        if !fresh && options.strict {
+            return Err(DatasetError::StaleGenerator {
+                expected: fingerprint,
+                found: stored.config.fingerprint,
+            });
+        }
+        if fresh
+            && stored.check::<T>().is_ok()
+            && stored.config.length == length
+            && stored.config.compression == compression
+        {
+            if !stored.is_current() {
+                write_config::<T>(&data_path, &stored.config)?;
             }
-            bincode::serialize_into(
-                File::create(small_block_path).expect("Database slice should be creatable"),
-                &first_block,
-            )
-            .expect("Database slice should be writable, and the type T should be serializable");
+            init_config = Some(stored.config);
         } else {
-            match &mut gen {
-                DataGenerator::Immut(x) => x.skip(small_block_count),
-                DataGenerator::Mut(x) => x.skip(small_block_count),
-            }
-        }
-    }
-    drop(first_block);
-
-    {
-        let mut i = block_count;
-        loop {
-            let path = data_path.join(format!("{i}.slice"));
-            if path
-                .try_exists()
-                .expect("Files in data path should be readable")
-            {
-                std::fs::remove_file(path).expect("Files in data path should be deletable");
-            } else {
-                break;
+            if !fresh {
+                log::warn!(
+                    "Dataset at {} was written by a different generator and will be regenerated",
+                    data_path.display()
+                );
             }
-            i += 1;
+            std::fs::remove_file(data_path.join("config.dat"))?;
         }
     }
 
-    for i in 1..(remaining_block_count + 1) {
+    let mut config = init_config.unwrap_or_else(|| AcademyDatasetConfig {
+        block_memory_size,
+        length,
+        compression,
+        fingerprint,
+        blocks: vec![],
+    });
+    config.block_memory_size = block_memory_size;
+    let mut progress = ProgressTracker::new(start, length, options.on_progress);
+
+    // Blocks recorded by an earlier run are kept if their slice still exists, and regenerated
+    // with the same items otherwise
+    for (i, block) in config.blocks.iter_mut().enumerate() {
         let file_path = data_path.join(format!("{i}.slice"));
-        if init_config.is_some() {
-            if file_path.exists() {
-                match &mut gen {
-                    DataGenerator::Immut(x) => x.skip(block_size),
-                    DataGenerator::Mut(x) => x.skip(block_size),
-                }
-                continue;
-            }
+        if file_path.exists() {
+            gen.skip(block.items);
+            progress.skipped(block.items, &file_path);
+        } else {
+            let items = gen.gen_range(block.first_item..block.first_item + block.items);
+            block.bytes = bincode::serialized_size(&items)?;
+            write_block(&file_path, &items, compression)?;
+            progress.written(block.items, &file_path);
         }
-        let block: Box<[T]> = match gen {
-            DataGenerator::Immut(x) => {
-                let out = (0..block_size).into_par_iter().map(|_| x.gen()).collect();
-                gen = DataGenerator::Immut(x);
-                out
+    }
+    config.fix_offsets();
+
+    // Items are generated in batches the size of the previous block, and each block takes items
+    // until it reaches block_memory_size bytes. Until the first block is written, the batches
+    // start with an item per thread and double each time.
+    let mut generated = config
+        .blocks
+        .last()
+        .map(|block| block.first_item + block.items)
+        .unwrap_or_default();
+    let mut writer = BlockWriter::new(data_path, config);
+    let mut last_config_write = Instant::now();
+    let mut first_batch = rayon::current_num_threads().max(1);
+    while generated < length {
+        let batch = match writer.config().blocks.last() {
+            Some(block) => block.items,
+            None => {
+                let batch = first_batch;
+                first_batch *= 2;
+                batch
             }
-            DataGenerator::Mut(x) => {
-                let out = (0..block_size).into_iter().map(|_| x.gen()).collect();
-                gen = DataGenerator::Mut(x);
-                out
+        }
+        .min(length - generated);
+        for item in gen.gen_range(generated..generated + batch).into_vec() {
+            if let Some((items, file_path)) = writer.push(item)? {
+                progress.written(items, &file_path);
+
+                // The config is rewritten every so often so that an interrupted run can resume
+                if last_config_write.elapsed() >= CONFIG_WRITE_INTERVAL {
+                    writer.write_config()?;
+                    last_config_write = Instant::now();
+                }
             }
-        };
-
-        bincode::serialize_into(
-            File::create(file_path).expect("Database slice should be creatable"),
-            &block,
-        )
-        .expect("Database slice should be writable, and the type T should be serializable");
+        }
+        generated += batch;
+    }
+    if let Some((items, file_path)) = writer.flush()? {
+        progress.written(items, &file_path);
     }
+    writer.finish()?;
+    Ok(())
 }
 
-// pub fn create_dataset_from_iter<T, I>(
-//     iter: impl IntoIterator<IntoIter = I>,
-//     data_path: PathBuf,
-//     block_memory_size: usize,
-// ) where
-//     T: Serialize + Send,
-//     I: ExactSizeIterator<Item = T> + Send,
-// {
-//     let mut iter = iter.into_iter();
-//     let length = iter.len();
-//     std::fs::create_dir_all(&data_path).expect("Data path directories should be creatable");
-//     let mut first_block = vec![];
-//     let mut block_size = 0usize;
-
-//     while let Some(item) = iter.next() {
-//         first_block.push(item);
-//         block_size += 1;
-//         if bincode::serialized_size(&first_block).expect("Type T should be serializable") as usize
-//             >= block_memory_size
-//         {
-//             break;
-//         }
-//     }
-//     bincode::serialize_into(
-//         File::create(data_path.join("0.slice")).expect("Database slice should be creatable"),
-//         &first_block,
-//     )
-//     .expect("Database slice should be writable, and the type T should be serializable");
-
-//     let config = AcademyDatasetConfig {
-//         block_memory_size,
-//         block_count: 1,
-//         block_size,
-//         length,
-//     };
-
-//     bincode::serialize_into(
-//         File::create(data_path.join("config.dat"))
-//             .expect("Database config should be creatable"),
-//         &config,
-//     )
-//     .expect("Database config should be writable");
-
-//     if block_size >= length {
-//         return;
-//     }
-
-//     let remaining_block_count = (length - block_size) / block_size;
-//     let small_block_count = (length - block_size) % block_size;
-//     first_block.clear();
-
-//     if small_block_count > 0 {
-//         for _ in 0..small_block_count {
-//             first_block.push(iter.next().unwrap());
-//         }
-//         bincode::serialize_into(
-//             File::create(data_path.join(format!("{}.slice", remaining_block_count + 1)))
-//                 .expect("Database slice should be creatable"),
-//             &first_block,
-//         )
-//         .expect("Database slice should be writable, and the type T should be serializable");
-//     }
-//     drop(first_block);
-
-//     let block_count = if small_block_count > 0 {
-//         remaining_block_count + 2
-//     } else {
-//         remaining_block_count + 1
-//     };
-
-//     {
-//         let mut i = block_count;
-//         loop {
-//             let path = data_path.join(format!("{i}.slice"));
-//             if path
-//                 .try_exists()
-//                 .expect("Files in data path should be readable")
-//             {
-//                 std::fs::remove_file(path).expect("Files in data path should be deletable");
-//             } else {
-//                 break;
-//             }
-//             i += 1;
-//         }
-//     }
-
-//     (1..(remaining_block_count + 1))
-//         .into_iter()
-//         .for_each(|i| {
-//             let block: Box<[T]> = (0..block_size)
-//                 .into_iter()
-//                 .map(|_| iter.next().expect("Iterator should not have exhausted"))
-//                 .collect();
-//             bincode::serialize_into(
-//                 File::create(data_path.join(format!("{i}.slice")))
-//                     .expect("Database slice should be creatable"),
-//                 &block,
-//             )
-//             .expect("Database slice should be writable, and the type T should be serializable");
-//         });
-// }
-
-
 #[cfg(test)]
 mod tests {
     use std::{collections::HashSet, sync::atomic::AtomicU8};
@@ -385,7 +458,7 @@ mod tests {
     use burn::data::dataset::Dataset;
     use tempfile::tempdir;
 
-    use super::{create_dataset, DataGen, AcademyDataset};
+    use super::{create_dataset, DataGen, AcademyDataset, DatasetError, IndexedDataGen};
 
     #[derive(Default)]
     struct ByteGen(AtomicU8);
@@ -435,4 +508,217 @@ mod tests {
             assert_eq!(db.get(i), None);
         }
     }
+
+    #[test]
+    fn test_type_mismatch_01() {
+        let dir = tempdir().unwrap();
+        let mut gen = ByteGen::default();
+        create_dataset(50, dir.path().into(), 20, super::DataGenerator::Immut(&mut gen));
+
+        let result = AcademyDataset::<(Vec<f32>, f32)>::try_new(dir.path().into(), 20);
+        assert!(matches!(result, Err(DatasetError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_migrate_legacy_config_01() {
+        let dir = tempdir().unwrap();
+        let mut gen = ByteGen::default();
+        create_dataset(50, dir.path().into(), 20, super::DataGenerator::Immut(&mut gen));
+
+        let config_path = dir.path().join("config.dat");
+        let config = super::read_config(dir.path()).unwrap().config;
+        let legacy_config = (
+            config.block_memory_size,
+            config.blocks.len(),
+            config.blocks[0].items,
+            config.length,
+        );
+        std::fs::write(&config_path, bincode::serialize(&legacy_config).unwrap()).unwrap();
+        assert!(!super::read_config(dir.path()).unwrap().is_current());
+
+        // Opening it as the wrong type fails without recording that type in the header
+        assert!(AcademyDataset::<u32>::try_new(dir.path().into(), 20).is_err());
+        assert!(!super::read_config(dir.path()).unwrap().is_current());
+
+        let db = AcademyDataset::<u8>::new(dir.path().into(), 20);
+        assert_eq!(db.len(), 50);
+        assert!(super::read_config(dir.path()).unwrap().is_current());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_use_compressed_db_01() {
+        let dir = tempdir().unwrap();
+        let mut gen = ByteGen::default();
+        super::create_dataset_with_options(
+            50,
+            dir.path().into(),
+            20,
+            super::DataGenerator::Immut(&mut gen),
+            super::DatasetOptions {
+                compression: super::Compression::Lz4,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let db = AcademyDataset::<u8>::new(dir.path().into(), 2);
+        let mut occurrences = [false; 50];
+        for i in 0..50 {
+            occurrences[db.get(i).unwrap() as usize] = true;
+        }
+        assert!(!occurrences.contains(&false));
+    }
+
+    #[test]
+    fn test_memory_budget_01() {
+        let dir = tempdir().unwrap();
+        let mut gen = ByteGen::default();
+        create_dataset(50, dir.path().into(), 20, super::DataGenerator::Immut(&mut gen));
+
+        let db = AcademyDataset::<u8>::new(dir.path().into(), 100);
+        assert_eq!(db.memory_limit(), 100);
+        assert_eq!(db.memory_usage(), 0);
+        for i in 0..50 {
+            db.get(i).unwrap();
+            assert!(db.memory_usage() > 0);
+            assert!(db.memory_usage() <= db.memory_limit());
+        }
+    }
+
+    struct MixGen;
+
+    impl IndexedDataGen for MixGen {
+        type Output = u64;
+
+        fn gen_at(&self, index: usize, seed: u64) -> Self::Output {
+            (index as u64).wrapping_mul(0x9e3779b97f4a7c15) ^ seed
+        }
+    }
+
+    #[test]
+    fn test_indexed_gen_01() {
+        let dir = tempdir().unwrap();
+        create_dataset(50, dir.path().into(), 40, super::DataGenerator::Indexed(&MixGen, 3));
+        let first = std::fs::read(dir.path().join("2.slice")).unwrap();
+
+        std::fs::remove_file(dir.path().join("2.slice")).unwrap();
+        create_dataset(50, dir.path().into(), 40, super::DataGenerator::Indexed(&MixGen, 3));
+        assert_eq!(std::fs::read(dir.path().join("2.slice")).unwrap(), first);
+
+        let db = AcademyDataset::<u64>::new(dir.path().into(), 100);
+        for i in 0..50 {
+            assert_eq!(db.get(i), Some(MixGen.gen_at(i, 3)));
+        }
+    }
+
+    struct ConstGen(u8);
+
+    impl DataGen for ConstGen {
+        type Output = u8;
+
+        fn gen(&self) -> Self::Output {
+            self.0
+        }
+
+        fn skip(&mut self, _num: usize) {}
+
+        fn fingerprint(&self) -> Option<u64> {
+            Some(self.0 as u64)
+        }
+    }
+
+    #[test]
+    fn test_stale_generator_01() {
+        let dir = tempdir().unwrap();
+        create_dataset(50, dir.path().into(), 20, super::DataGenerator::Immut(&mut ConstGen(1)));
+        create_dataset(50, dir.path().into(), 20, super::DataGenerator::Immut(&mut ConstGen(2)));
+        let db = AcademyDataset::<u8>::new(dir.path().into(), 100);
+        assert!((0..50).all(|i| db.get(i) == Some(2)));
+
+        let result = super::create_dataset_with_options(
+            50,
+            dir.path().into(),
+            20,
+            super::DataGenerator::Immut(&mut ConstGen(3)),
+            super::DatasetOptions {
+                strict: true,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(DatasetError::StaleGenerator { .. })));
+    }
+
+    #[test]
+    fn test_progress_01() {
+        let dir = tempdir().unwrap();
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let options = || {
+            let reports = reports.clone();
+            super::DatasetOptions {
+                on_progress: Some(std::sync::Arc::new(move |progress| {
+                    reports.lock().unwrap().push(progress.clone())
+                })),
+                ..Default::default()
+            }
+        };
+        let mut gen = ByteGen::default();
+        super::create_dataset_with_options(
+            50,
+            dir.path().into(),
+            20,
+            super::DataGenerator::Immut(&mut gen),
+            options(),
+        )
+        .unwrap();
+        let last = reports.lock().unwrap().last().unwrap().clone();
+        assert!(last.is_done());
+        assert_eq!((last.blocks_written, last.blocks_skipped), (5, 0));
+        assert_eq!(last.items_written, 50);
+        assert!(last.bytes_on_disk > 0);
+
+        std::fs::remove_file(dir.path().join("2.slice")).unwrap();
+        reports.lock().unwrap().clear();
+        super::create_dataset_with_options(
+            50,
+            dir.path().into(),
+            20,
+            super::DataGenerator::Immut(&mut gen),
+            options(),
+        )
+        .unwrap();
+        let last = reports.lock().unwrap().last().unwrap().clone();
+        assert_eq!((last.blocks_written, last.blocks_skipped), (1, 4));
+        assert_eq!((last.items_written, last.items_skipped), (12, 38));
+    }
+
+    struct SeqGen;
+
+    impl IndexedDataGen for SeqGen {
+        type Output = Vec<u8>;
+
+        fn gen_at(&self, index: usize, _seed: u64) -> Self::Output {
+            // Later items are much larger than the first ones
+            vec![index as u8; if index < 10 { 1 } else { 40 }]
+        }
+    }
+
+    #[test]
+    fn test_variable_size_01() {
+        let dir = tempdir().unwrap();
+        create_dataset(50, dir.path().into(), 100, super::DataGenerator::Indexed(&SeqGen, 0));
+
+        let config = super::read_config(dir.path()).unwrap().config;
+        assert_eq!(config.blocks.iter().map(|block| block.items).sum::<usize>(), 50);
+        for block in &config.blocks[1..] {
+            // Every block ends with the first item that takes it to block_memory_size
+            assert!(block.bytes < 100 + 48, "{block:?}");
+        }
+
+        let db = AcademyDataset::<Vec<u8>>::new(dir.path().into(), 1000);
+        for i in 0..50 {
+            assert_eq!(db.get(i), Some(SeqGen.gen_at(i, 0)));
+        }
+        assert_eq!(db.get(50), None);
+    }
 }
@@ -0,0 +1,304 @@
+use std::path::Path;
+
+use burn::{
+    config::Config,
+    data::dataset::Dataset,
+    tensor::{backend::Backend, Data, Shape, Tensor},
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{stats::item_values, AcademyDataset, DatasetError, DatasetStats, FeatureStats};
+
+pub(crate) const SCALER_FILE: &str = "scaler.json";
+/// The most items read to estimate the quantiles used by [`Normalization::Robust`]
+const ROBUST_SAMPLE_ITEMS: usize = 10_000;
+
+/// How inputs and targets are scaled before they reach the model.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    #[default]
+    None,
+    /// Subtract the mean and divide by the standard deviation
+    Standard,
+    /// Map the minimum to 0 and the maximum to 1
+    MinMax,
+    /// Subtract the median and divide by the interquartile range, which outliers barely move
+    Robust,
+}
+
+/// Per-feature offsets and scales fitted on a training dataset, applied as
+/// `(x - offset) / scale`.
+///
+/// Input offsets and scales apply to the last dimension of the inputs, and target offsets and
+/// scales to the last dimension of the targets.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Scaler {
+    pub normalization: Normalization,
+    pub input_offsets: Vec<f32>,
+    pub input_scales: Vec<f32>,
+    pub target_offsets: Vec<f32>,
+    pub target_scales: Vec<f32>,
+}
+
+impl Config for Scaler {}
+
+/// Keeps constant features, whose scale would be 0, from turning into NaNs.
+fn usable_scale(scale: f32) -> f32 {
+    if scale.is_finite() && scale > 0.0 {
+        scale
+    } else {
+        1.0
+    }
+}
+
+/// The `(offset, scale)` of each feature for the median and interquartile range of `values`.
+fn quantile_scales(values: Vec<Vec<f32>>) -> (Vec<f32>, Vec<f32>) {
+    values
+        .into_iter()
+        .map(|mut values| {
+            values.retain(|x| !x.is_nan());
+            values.sort_unstable_by(f32::total_cmp);
+            let quantile = |q: f64| {
+                values
+                    .get(((values.len().saturating_sub(1)) as f64 * q).round() as usize)
+                    .copied()
+                    .unwrap_or_default()
+            };
+            (quantile(0.5), usable_scale(quantile(0.75) - quantile(0.25)))
+        })
+        .unzip()
+}
+
+impl Scaler {
+    /// Fits `normalization` using statistics from [`DatasetStats`]. Robust scaling needs quantiles,
+    /// which the statistics do not hold, so it falls back to standard scaling.
+    pub fn from_stats(stats: &DatasetStats, normalization: Normalization) -> Self {
+        let normalization = match normalization {
+            Normalization::Robust => {
+                log::warn!(
+                    "Robust scaling can not be fitted from statistics, using standard scaling"
+                );
+                Normalization::Standard
+            }
+            normalization => normalization,
+        };
+        let scales = |features: &[FeatureStats]| -> (Vec<f32>, Vec<f32>) {
+            features
+                .iter()
+                .map(|stats| match normalization {
+                    Normalization::None | Normalization::Robust => (0.0, 1.0),
+                    Normalization::Standard => {
+                        (stats.mean as f32, usable_scale(stats.std_dev as f32))
+                    }
+                    Normalization::MinMax => (stats.min, usable_scale(stats.max - stats.min)),
+                })
+                .unzip()
+        };
+        let (input_offsets, input_scales) = scales(&stats.inputs);
+        let (target_offsets, target_scales) = scales(&stats.targets);
+        Self {
+            normalization,
+            input_offsets,
+            input_scales,
+            target_offsets,
+            target_scales,
+        }
+    }
+
+    /// Fits `normalization` on every item of `dataset`, or on an evenly spaced sample of them for
+    /// robust scaling.
+    pub fn fit<B, I, D>(dataset: &D, normalization: Normalization) -> Self
+    where
+        B: Backend,
+        I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+        D: Dataset<I>,
+    {
+        if normalization != Normalization::Robust {
+            return Self::from_stats(&DatasetStats::compute_on::<B, I, D>(dataset), normalization);
+        }
+
+        let step = dataset.len().div_ceil(ROBUST_SAMPLE_ITEMS).max(1);
+        let samples: Vec<_> = (0..dataset.len())
+            .step_by(step)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .filter_map(|i| dataset.get(i).map(item_values::<B, I>))
+            .collect();
+        let mut inputs: Vec<Vec<f32>> = vec![];
+        let mut targets: Vec<Vec<f32>> = vec![];
+        for (input, features, target) in samples {
+            if inputs.len() < features {
+                inputs.resize(features, vec![]);
+            }
+            for (j, x) in input.into_iter().enumerate() {
+                inputs[j % features].push(x);
+            }
+            if targets.len() < target.len() {
+                targets.resize(target.len(), vec![]);
+            }
+            for (y, values) in target.into_iter().zip(&mut targets) {
+                values.push(y);
+            }
+        }
+
+        let (input_offsets, input_scales) = quantile_scales(inputs);
+        let (target_offsets, target_scales) = quantile_scales(targets);
+        Self {
+            normalization,
+            input_offsets,
+            input_scales,
+            target_offsets,
+            target_scales,
+        }
+    }
+
+    /// Like [`Scaler::fit`], but reuses the statistics saved next to `dataset`, computing them if
+    /// needed.
+    pub fn fit_dataset<B, I>(
+        dataset: &AcademyDataset<I>,
+        normalization: Normalization,
+    ) -> Result<Self, DatasetError>
+    where
+        B: Backend,
+        I: DeserializeOwned + Send + Sync + Clone + Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    {
+        match normalization {
+            Normalization::Robust => Ok(Self::fit::<B, I, _>(dataset, normalization)),
+            normalization => Ok(Self::from_stats(
+                &DatasetStats::load_or_compute::<B, I>(dataset)?,
+                normalization,
+            )),
+        }
+    }
+
+    /// Loads the scaler saved in `artifact_dir` by training.
+    pub fn load_from(artifact_dir: &Path) -> Result<Self, DatasetError> {
+        Self::load(artifact_dir.join(SCALER_FILE))
+            .map_err(|e| DatasetError::Malformed(format!("{SCALER_FILE} could not be read: {e}")))
+    }
+
+    pub fn save_to(&self, artifact_dir: &Path) -> Result<(), DatasetError> {
+        Ok(self.save(artifact_dir.join(SCALER_FILE))?)
+    }
+
+    fn apply<B: Backend, const D: usize>(
+        tensor: Tensor<B, D>,
+        offsets: &[f32],
+        scales: &[f32],
+        inverse: bool,
+    ) -> Tensor<B, D> {
+        let dims = tensor.dims();
+        assert_eq!(
+            dims[D - 1],
+            offsets.len(),
+            "Scaler should have been fitted on items with as many features"
+        );
+        let mut shape = [1; D];
+        shape[D - 1] = offsets.len();
+        let device = tensor.device();
+        let vector = |values: &[f32]| {
+            Tensor::<B, D>::from_data_device(
+                Data::new(values.to_vec(), Shape::new(shape)).convert(),
+                &device,
+            )
+        };
+        if inverse {
+            tensor.mul(vector(scales)).add(vector(offsets))
+        } else {
+            tensor.sub(vector(offsets)).div(vector(scales))
+        }
+    }
+
+    pub fn normalize_inputs<B: Backend, const D: usize>(
+        &self,
+        inputs: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        Self::apply(inputs, &self.input_offsets, &self.input_scales, false)
+    }
+
+    /// Like [`Scaler::normalize_inputs`] for raw values whose last dimension is the features.
+    pub fn normalize_input_values(&self, values: &mut [f32]) {
+        let features = self.input_offsets.len();
+        assert_eq!(
+            values.len() % features.max(1),
+            0,
+            "Scaler should have been fitted on items with as many features"
+        );
+        for (j, x) in values.iter_mut().enumerate() {
+            *x = (*x - self.input_offsets[j % features]) / self.input_scales[j % features];
+        }
+    }
+
+    pub fn normalize_targets<B: Backend, const D: usize>(
+        &self,
+        targets: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        Self::apply(targets, &self.target_offsets, &self.target_scales, false)
+    }
+
+    /// Maps normalized targets, such as the outputs of a model trained with this scaler, back to
+    /// the units of the dataset.
+    pub fn denormalize_targets<B: Backend, const D: usize>(
+        &self,
+        targets: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        Self::apply(targets, &self.target_offsets, &self.target_scales, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{
+        data::dataset::InMemDataset,
+        tensor::{Data, Tensor},
+    };
+    use burn_ndarray::NdArray;
+
+    use super::{Normalization, Scaler};
+    use crate::data::RegressionItem;
+
+    #[test]
+    fn scaler_01() {
+        let items: Vec<_> = (0..101)
+            .map(|i| RegressionItem {
+                inputs: vec![i as f32, 5.0],
+                input_shape: [1, 2],
+                // An outlier that robust scaling should ignore
+                targets: vec![if i == 100 { 1e6 } else { i as f32 * 2.0 }],
+                weight: None,
+            })
+            .collect();
+        let dataset = InMemDataset::new(items);
+
+        let standard = Scaler::fit::<NdArray, _, _>(&dataset, Normalization::Standard);
+        assert_eq!(standard.input_offsets, [50.0, 5.0]);
+        // The constant feature is left unscaled rather than divided by 0
+        assert_eq!(standard.input_scales[1], 1.0);
+
+        let min_max = Scaler::fit::<NdArray, _, _>(&dataset, Normalization::MinMax);
+        let inputs = Tensor::<NdArray, 2>::from_floats([[0.0, 5.0], [100.0, 5.0]]);
+        assert_eq!(
+            min_max.normalize_inputs(inputs).into_data(),
+            Data::from([[0.0, 0.0], [1.0, 0.0]])
+        );
+        let mut values = [0.0, 5.0, 100.0, 5.0];
+        min_max.normalize_input_values(&mut values);
+        assert_eq!(values, [0.0, 0.0, 1.0, 0.0]);
+
+        let robust = Scaler::fit::<NdArray, _, _>(&dataset, Normalization::Robust);
+        assert_eq!(robust.target_offsets, [100.0]);
+        assert_eq!(robust.target_scales, [100.0]);
+        let targets = Tensor::<NdArray, 2>::from_floats([[0.5], [-1.0]]);
+        assert_eq!(
+            robust.denormalize_targets(targets.clone()).into_data(),
+            Data::from([[150.0], [0.0]])
+        );
+        assert_eq!(
+            robust
+                .normalize_targets(robust.denormalize_targets(targets.clone()))
+                .into_data(),
+            targets.into_data()
+        );
+    }
+}
@@ -0,0 +1,575 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use burn::{
+    data::dataset::Dataset,
+    tensor::{backend::Backend, Tensor},
+};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{BlockWriter, Compression, DatasetError, RegressionItem};
+
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+/// The size written headers are padded to, so that the row count can be filled in once every row
+/// has been written without moving the data
+const WRITE_HEADER_LEN: usize = 256;
+/// The names of the arrays in `.npz` files written by [`export_npz`]
+const NPZ_INPUTS: &str = "inputs";
+const NPZ_TARGETS: &str = "targets";
+
+impl From<zip::result::ZipError> for DatasetError {
+    fn from(value: zip::result::ZipError) -> Self {
+        match value {
+            zip::result::ZipError::Io(e) => Self::Io(e),
+            e => Self::Malformed(format!("npz archive could not be read: {e}")),
+        }
+    }
+}
+
+/// The element type of an array, such as `<f4`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Dtype {
+    kind: u8,
+    size: usize,
+    big_endian: bool,
+}
+
+impl Dtype {
+    fn parse(descr: &str) -> Result<Self, DatasetError> {
+        let unsupported =
+            || DatasetError::Malformed(format!("npy dtype `{descr}` is not supported"));
+        let bytes = descr.as_bytes();
+        if bytes.len() < 3 {
+            return Err(unsupported());
+        }
+        let dtype = Self {
+            kind: bytes[1],
+            size: descr[2..].parse().map_err(|_| unsupported())?,
+            big_endian: bytes[0] == b'>',
+        };
+        match (dtype.kind, dtype.size) {
+            (b'f', 4 | 8) | (b'i' | b'u', 1 | 2 | 4 | 8) => Ok(dtype),
+            _ => Err(unsupported()),
+        }
+    }
+
+    fn to_f32(self, bytes: &[u8]) -> f32 {
+        let mut buf = [0u8; 8];
+        buf[..self.size].copy_from_slice(bytes);
+        if self.big_endian {
+            buf[..self.size].reverse();
+        }
+        match (self.kind, self.size) {
+            (b'f', 4) => f32::from_le_bytes(buf[..4].try_into().unwrap()),
+            (b'f', _) => f64::from_le_bytes(buf) as f32,
+            (b'i', 1) => buf[0] as i8 as f32,
+            (b'i', 2) => i16::from_le_bytes(buf[..2].try_into().unwrap()) as f32,
+            (b'i', 4) => i32::from_le_bytes(buf[..4].try_into().unwrap()) as f32,
+            (b'i', _) => i64::from_le_bytes(buf) as f32,
+            (_, _) => u64::from_le_bytes(buf) as f32,
+        }
+    }
+}
+
+/// Finds the value of `key` in the header dictionary of an npy file.
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str, DatasetError> {
+    let missing = || DatasetError::Malformed(format!("npy header has no `{key}`"));
+    let start = header.find(&format!("'{key}'")).ok_or_else(missing)? + key.len() + 2;
+    let value = header[start..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?;
+    Ok(value.trim_start())
+}
+
+/// Reads an npy array row by row, converting its elements to `f32`.
+pub struct NpyReader<R> {
+    reader: R,
+    dtype: Dtype,
+    shape: Vec<usize>,
+    rows_left: usize,
+    buf: Vec<u8>,
+}
+
+impl NpyReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, DatasetError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> NpyReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, DatasetError> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic[..6] != NPY_MAGIC {
+            return Err(DatasetError::Malformed(
+                "npy file does not start with the npy magic string".into(),
+            ));
+        }
+        let header_len = if magic[6] == 1 {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        } else {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        };
+        let mut header = vec![0u8; header_len];
+        reader.read_exact(&mut header)?;
+        let header = String::from_utf8_lossy(&header);
+
+        let descr = header_value(&header, "descr")?;
+        let descr = descr
+            .get(1..)
+            .and_then(|descr| descr.split(['\'', '"']).next())
+            .unwrap_or_default();
+        let dtype = Dtype::parse(descr)?;
+        if header_value(&header, "fortran_order")?.starts_with("True") {
+            return Err(DatasetError::Malformed(
+                "npy arrays in Fortran order are not supported".into(),
+            ));
+        }
+        let shape = header_value(&header, "shape")?;
+        let shape = shape
+            .strip_prefix('(')
+            .and_then(|shape| shape.split(')').next())
+            .ok_or_else(|| DatasetError::Malformed(format!("npy shape `{shape}` is malformed")))?
+            .split(',')
+            .map(str::trim)
+            .filter(|dim| !dim.is_empty())
+            .map(|dim| {
+                dim.parse().map_err(|_| {
+                    DatasetError::Malformed(format!("npy shape has a malformed dimension `{dim}`"))
+                })
+            })
+            .collect::<Result<Vec<usize>, _>>()?;
+        if shape.is_empty() {
+            return Err(DatasetError::Malformed(
+                "npy arrays of scalars have no rows".into(),
+            ));
+        }
+
+        Ok(Self {
+            reader,
+            dtype,
+            rows_left: shape[0],
+            shape,
+            buf: vec![],
+        })
+    }
+
+    /// The shape of the whole array. The first dimension is the number of rows.
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The number of elements in each row.
+    pub fn row_len(&self) -> usize {
+        self.shape[1..].iter().product()
+    }
+
+    /// Reads the next row into `row`, returning false once every row has been read.
+    pub fn read_row(&mut self, row: &mut Vec<f32>) -> Result<bool, DatasetError> {
+        if self.rows_left == 0 {
+            return Ok(false);
+        }
+        self.rows_left -= 1;
+        self.buf.resize(self.row_len() * self.dtype.size, 0);
+        self.reader.read_exact(&mut self.buf)?;
+        row.clear();
+        row.extend(
+            self.buf
+                .chunks_exact(self.dtype.size)
+                .map(|bytes| self.dtype.to_f32(bytes)),
+        );
+        Ok(true)
+    }
+}
+
+/// Writes an npy array of `f32`s row by row.
+pub struct NpyWriter<W: Write + Seek> {
+    writer: W,
+    row_shape: Vec<usize>,
+    rows: usize,
+}
+
+impl NpyWriter<BufWriter<File>> {
+    pub fn create(path: &Path, row_shape: &[usize]) -> Result<Self, DatasetError> {
+        Self::new(BufWriter::new(File::create(path)?), row_shape)
+    }
+}
+
+impl<W: Write + Seek> NpyWriter<W> {
+    /// Starts an array whose rows have the shape `row_shape`.
+    pub fn new(mut writer: W, row_shape: &[usize]) -> Result<Self, DatasetError> {
+        writer.write_all(&Self::header(0, row_shape)?)?;
+        Ok(Self {
+            writer,
+            row_shape: row_shape.to_vec(),
+            rows: 0,
+        })
+    }
+
+    fn header(rows: usize, row_shape: &[usize]) -> Result<Vec<u8>, DatasetError> {
+        let shape = std::iter::once(rows)
+            .chain(row_shape.iter().copied())
+            .map(|dim| format!("{dim},"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let dict = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({shape}), }}");
+        let header_len = WRITE_HEADER_LEN - NPY_MAGIC.len() - 4;
+        if dict.len() >= header_len {
+            return Err(DatasetError::Malformed(format!(
+                "npy shape {shape} is too long to be written"
+            )));
+        }
+
+        let mut header = NPY_MAGIC.to_vec();
+        header.extend_from_slice(&[1, 0]);
+        header.extend_from_slice(&(header_len as u16).to_le_bytes());
+        header.extend_from_slice(format!("{dict:<width$}\n", width = header_len - 1).as_bytes());
+        Ok(header)
+    }
+
+    pub fn write_row(&mut self, row: &[f32]) -> Result<(), DatasetError> {
+        let row_len: usize = self.row_shape.iter().product();
+        if row.len() != row_len {
+            return Err(DatasetError::Malformed(format!(
+                "npy rows should have {row_len} elements, but one has {}",
+                row.len()
+            )));
+        }
+        for x in row {
+            self.writer.write_all(&x.to_le_bytes())?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Fills in the number of rows written, returning the underlying writer.
+    pub fn finish(mut self) -> Result<W, DatasetError> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer
+            .write_all(&Self::header(self.rows, &self.row_shape)?)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn import_arrays(
+    mut inputs: NpyReader<impl Read>,
+    mut targets: NpyReader<impl Read>,
+    data_path: &Path,
+    block_memory_size: usize,
+    compression: Compression,
+) -> Result<usize, DatasetError> {
+    if inputs.shape()[0] != targets.shape()[0] {
+        return Err(DatasetError::Malformed(format!(
+            "npy inputs have {} rows but targets have {}",
+            inputs.shape()[0],
+            targets.shape()[0]
+        )));
+    }
+    let input_shape = match inputs.shape() {
+        [_] => [1, 1],
+        [_, features] => [1, *features],
+        [_, rows, features] => [*rows, *features],
+        shape => {
+            return Err(DatasetError::Malformed(format!(
+                "npy inputs should have at most 3 dimensions, but have shape {shape:?}"
+            )))
+        }
+    };
+
+    let mut writer = BlockWriter::create(data_path, block_memory_size, compression)?;
+    let mut input = vec![];
+    let mut target = vec![];
+    while inputs.read_row(&mut input)? && targets.read_row(&mut target)? {
+        writer.push(RegressionItem {
+            inputs: input.clone(),
+            input_shape,
+            targets: target.clone(),
+            weight: None,
+        })?;
+    }
+    Ok(writer.finish()?.length)
+}
+
+/// Writes a dataset of [`RegressionItem`]s at `data_path` from the rows of two npy arrays,
+/// replacing any dataset already there. Returns the number of items written.
+///
+/// Inputs of shape `(n, rows, features)` keep their shape, while inputs of shape `(n, features)`
+/// become a single row. Targets of shape `(n,)` have one target per item. The arrays are read row
+/// by row, so they do not need to fit in memory.
+pub fn import_npy(
+    inputs_path: &Path,
+    targets_path: &Path,
+    data_path: &Path,
+    block_memory_size: usize,
+    compression: Compression,
+) -> Result<usize, DatasetError> {
+    import_arrays(
+        NpyReader::open(inputs_path)?,
+        NpyReader::open(targets_path)?,
+        data_path,
+        block_memory_size,
+        compression,
+    )
+}
+
+/// Like [`import_npy`], but reads the arrays named `inputs` and `targets` out of an npz archive,
+/// as written by `numpy.savez`.
+pub fn import_npz(
+    npz_path: &Path,
+    inputs: &str,
+    targets: &str,
+    data_path: &Path,
+    block_memory_size: usize,
+    compression: Compression,
+) -> Result<usize, DatasetError> {
+    // Each array is read through its own handle, as the archive only lends out one at a time
+    let mut input_archive = ZipArchive::new(BufReader::new(File::open(npz_path)?))?;
+    let mut target_archive = ZipArchive::new(BufReader::new(File::open(npz_path)?))?;
+    let inputs = input_archive.by_name(&format!("{inputs}.npy"))?;
+    let targets = target_archive.by_name(&format!("{targets}.npy"))?;
+    let length = import_arrays(
+        NpyReader::new(inputs)?,
+        NpyReader::new(targets)?,
+        data_path,
+        block_memory_size,
+        compression,
+    )?;
+    Ok(length)
+}
+
+fn export_arrays<B, I, D, W>(
+    dataset: &D,
+    inputs: impl FnOnce(&[usize]) -> Result<NpyWriter<W>, DatasetError>,
+    targets: impl FnOnce(&[usize]) -> Result<NpyWriter<W>, DatasetError>,
+) -> Result<(W, W), DatasetError>
+where
+    B: Backend,
+    I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    D: Dataset<I>,
+    W: Write + Seek,
+{
+    // Rows are only written in order, so a missing item fails the export rather than shifting
+    // every row after it
+    let item = |i: usize| {
+        dataset
+            .get(i)
+            .map(Into::<(Tensor<B, 2>, Tensor<B, 1>)>::into)
+            .ok_or_else(|| {
+                DatasetError::Malformed(format!(
+                    "item {i} of the {} being exported could not be read",
+                    dataset.len()
+                ))
+            })
+    };
+    let first = (!dataset.is_empty()).then(|| item(0)).transpose()?;
+    let input_shape = first
+        .as_ref()
+        .map(|(input, _)| input.dims().to_vec())
+        .unwrap_or_default();
+    let target_len = first
+        .as_ref()
+        .map(|(_, target)| target.dims()[0])
+        .unwrap_or_default();
+    let mut inputs = inputs(&input_shape)?;
+    let mut targets = targets(&[target_len])?;
+    for i in 0..dataset.len() {
+        let (input, target) = item(i)?;
+        if input.dims()[..] != input_shape[..] {
+            return Err(DatasetError::Malformed(format!(
+                "npy exports need items of the same shape, but found {:?} and {input_shape:?}",
+                input.dims()
+            )));
+        }
+        inputs.write_row(&input.into_data().convert::<f32>().value)?;
+        targets.write_row(&target.into_data().convert::<f32>().value)?;
+    }
+    Ok((inputs.finish()?, targets.finish()?))
+}
+
+/// Writes the inputs and targets of every item of `dataset` to two npy arrays of shape
+/// `(n, rows, features)` and `(n, targets)`.
+pub fn export_npy<B, I, D>(
+    dataset: &D,
+    inputs_path: &Path,
+    targets_path: &Path,
+) -> Result<(), DatasetError>
+where
+    B: Backend,
+    I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    D: Dataset<I>,
+{
+    export_arrays::<B, I, D, _>(
+        dataset,
+        |shape| NpyWriter::create(inputs_path, shape),
+        |shape| NpyWriter::create(targets_path, shape),
+    )?;
+    Ok(())
+}
+
+/// Like [`export_npy`], but writes both arrays into an npz archive as `inputs` and `targets`, so
+/// that `numpy.load(npz_path)["inputs"]` reads the inputs.
+pub fn export_npz<B, I, D>(dataset: &D, npz_path: &Path) -> Result<(), DatasetError>
+where
+    B: Backend,
+    I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    D: Dataset<I>,
+{
+    // The row counts are only known at the end, so the arrays are written to seekable temporary
+    // files before being copied into the archive
+    let (inputs, targets) = export_arrays::<B, I, D, _>(
+        dataset,
+        |shape| NpyWriter::new(BufWriter::new(tempfile::tempfile()?), shape),
+        |shape| NpyWriter::new(BufWriter::new(tempfile::tempfile()?), shape),
+    )?;
+    let mut archive = ZipWriter::new(BufWriter::new(File::create(npz_path)?));
+    for (name, array) in [(NPZ_INPUTS, inputs), (NPZ_TARGETS, targets)] {
+        let mut array = array.into_inner().map_err(|e| e.into_error())?;
+        let len = array.stream_position()?;
+        array.rewind()?;
+        archive.start_file(
+            format!("{name}.npy"),
+            FileOptions::default()
+                .compression_method(CompressionMethod::Stored)
+                .large_file(len > u32::MAX as u64),
+        )?;
+        std::io::copy(&mut array, &mut archive)?;
+    }
+    archive.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Cursor};
+
+    use burn::data::dataset::{Dataset, InMemDataset};
+    use burn_ndarray::NdArray;
+    use tempfile::tempdir;
+
+    use super::{export_npy, export_npz, import_npz, NpyReader, NpyWriter};
+    #[cfg(target_endian = "little")]
+    use crate::data::{create_mapped_dataset, MappedDataset};
+    use crate::data::{AcademyDataset, Compression, RegressionItem};
+
+    #[test]
+    fn npy_01() {
+        let mut writer = NpyWriter::new(Cursor::new(vec![]), &[2, 3]).unwrap();
+        writer.write_row(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        writer.write_row(&[6.0; 6]).unwrap();
+        assert!(writer.write_row(&[0.0]).is_err());
+        let bytes = writer.finish().unwrap().into_inner();
+        assert_eq!(bytes.len(), 256 + 12 * 4);
+
+        let mut reader = NpyReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.shape(), &[2, 2, 3]);
+        let mut row = vec![];
+        assert!(reader.read_row(&mut row).unwrap());
+        assert_eq!(row, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(reader.read_row(&mut row).unwrap());
+        assert!(!reader.read_row(&mut row).unwrap());
+
+        // A 1-D array of big endian f64, as numpy writes them
+        let header = "{'descr': '>f8', 'fortran_order': False, 'shape': (2,), }";
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&1.5f64.to_be_bytes());
+        bytes.extend_from_slice(&(-2.0f64).to_be_bytes());
+        let mut reader = NpyReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.row_len(), 1);
+        assert!(reader.read_row(&mut row).unwrap());
+        assert_eq!(row, [1.5]);
+        assert!(reader.read_row(&mut row).unwrap());
+        assert_eq!(row, [-2.0]);
+    }
+
+    #[test]
+    fn npz_roundtrip_01() {
+        let dir = tempdir().unwrap();
+        let npz_path = dir.path().join("data.npz");
+        let data_path = dir.path().join("data");
+        let items: Vec<_> = (0..50)
+            .map(|i| RegressionItem {
+                inputs: (0..4).map(|j| (i * 4 + j) as f32).collect(),
+                input_shape: [2, 2],
+                targets: vec![i as f32, -(i as f32)],
+                weight: None,
+            })
+            .collect();
+        export_npz::<NdArray, _, _>(&InMemDataset::new(items.clone()), &npz_path).unwrap();
+
+        let length = import_npz(
+            &npz_path,
+            "inputs",
+            "targets",
+            &data_path,
+            200,
+            Compression::None,
+        )
+        .unwrap();
+        assert_eq!(length, 50);
+        let db = AcademyDataset::<RegressionItem>::new(data_path, 1 << 20);
+        assert!(db.block_count() > 1);
+        assert_eq!(
+            (0..50).map(|i| db.get(i).unwrap()).collect::<Vec<_>>(),
+            items
+        );
+
+        // Rows would stop lining up with the items if a missing one were skipped
+        struct Gap(InMemDataset<RegressionItem>);
+
+        impl Dataset<RegressionItem> for Gap {
+            fn get(&self, index: usize) -> Option<RegressionItem> {
+                (index != 1).then(|| self.0.get(index)).flatten()
+            }
+
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+        }
+
+        assert!(export_npz::<NdArray, _, _>(&Gap(InMemDataset::new(items)), &npz_path).is_err());
+    }
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn npy_export_mapped_01() {
+        let dir = tempdir().unwrap();
+        let mapped_path = dir.path().join("mapped");
+        std::fs::create_dir(&mapped_path).unwrap();
+        create_mapped_dataset(
+            mapped_path.clone(),
+            [1, 2],
+            1,
+            (0..3).map(|i| (vec![i as f32; 2], [-(i as f32)])),
+        );
+        let (inputs_path, targets_path) = (dir.path().join("x.npy"), dir.path().join("y.npy"));
+        export_npy::<NdArray, _, _>(
+            &MappedDataset::new(mapped_path),
+            &inputs_path,
+            &targets_path,
+        )
+        .unwrap();
+
+        let mut reader = NpyReader::new(File::open(&inputs_path).unwrap()).unwrap();
+        assert_eq!(reader.shape(), &[3, 1, 2]);
+        let mut row = vec![];
+        for i in 0..3 {
+            assert!(reader.read_row(&mut row).unwrap());
+            assert_eq!(row, [i as f32; 2]);
+        }
+        let reader = NpyReader::new(File::open(&targets_path).unwrap()).unwrap();
+        assert_eq!(reader.shape(), &[3, 1]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How far along [`create_dataset`](super::create_dataset) is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreationProgress {
+    /// The number of items the dataset will hold
+    pub length: usize,
+    /// Blocks generated and written by this run
+    pub blocks_written: usize,
+    /// Blocks that were already on disk from an earlier run
+    pub blocks_skipped: usize,
+    pub items_written: usize,
+    pub items_skipped: usize,
+    /// The total size of the blocks written or skipped so far
+    pub bytes_on_disk: u64,
+    pub elapsed: Duration,
+    /// The generation rate of this run, not counting skipped items
+    pub items_per_second: f64,
+    /// The estimated time left, if any items have been generated yet
+    pub eta: Option<Duration>,
+}
+
+impl CreationProgress {
+    pub fn is_done(&self) -> bool {
+        self.items_written + self.items_skipped >= self.length
+    }
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&CreationProgress) + Send + Sync>;
+
+/// How often progress is logged. The callback is called after every block.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+pub(crate) struct ProgressTracker {
+    progress: CreationProgress,
+    start: Instant,
+    last_log: Option<Instant>,
+    callback: Option<ProgressCallback>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(start: Instant, length: usize, callback: Option<ProgressCallback>) -> Self {
+        Self {
+            progress: CreationProgress {
+                length,
+                blocks_written: 0,
+                blocks_skipped: 0,
+                items_written: 0,
+                items_skipped: 0,
+                bytes_on_disk: 0,
+                elapsed: Duration::ZERO,
+                items_per_second: 0.0,
+                eta: None,
+            },
+            start,
+            last_log: None,
+            callback,
+        }
+    }
+
+    pub(crate) fn written(&mut self, items: usize, slice_path: &Path) {
+        self.progress.blocks_written += 1;
+        self.progress.items_written += items;
+        self.report(slice_path);
+    }
+
+    pub(crate) fn skipped(&mut self, items: usize, slice_path: &Path) {
+        self.progress.blocks_skipped += 1;
+        self.progress.items_skipped += items;
+        self.report(slice_path);
+    }
+
+    fn report(&mut self, slice_path: &Path) {
+        let progress = &mut self.progress;
+        progress.bytes_on_disk += std::fs::metadata(slice_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        progress.elapsed = self.start.elapsed();
+        progress.items_per_second =
+            progress.items_written as f64 / progress.elapsed.as_secs_f64().max(f64::EPSILON);
+
+        let remaining = progress
+            .length
+            .saturating_sub(progress.items_written + progress.items_skipped);
+        progress.eta = (progress.items_written > 0)
+            .then(|| Duration::from_secs_f64(remaining as f64 / progress.items_per_second));
+
+        let now = Instant::now();
+        if progress.is_done()
+            || self
+                .last_log
+                .map(|last_log| now - last_log >= LOG_INTERVAL)
+                .unwrap_or(true)
+        {
+            self.last_log = Some(now);
+            log::info!(
+                "Dataset items: {}/{} ({} blocks written, {} resumed), {:.0} items/s, {} bytes on disk, ETA {}",
+                progress.items_written + progress.items_skipped,
+                progress.length,
+                progress.blocks_written,
+                progress.blocks_skipped,
+                progress.items_per_second,
+                progress.bytes_on_disk,
+                progress
+                    .eta
+                    .map(|eta| format!("{}s", eta.as_secs()))
+                    .unwrap_or_else(|| "unknown".into())
+            );
+        }
+
+        if let Some(callback) = &self.callback {
+            callback(progress);
+        }
+    }
+}
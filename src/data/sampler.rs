@@ -0,0 +1,302 @@
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, SyncSender},
+        Arc, RwLock,
+    },
+    thread::JoinHandle,
+};
+
+use burn::data::dataset::Dataset;
+use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+use serde::de::DeserializeOwned;
+
+use super::AcademyDataset;
+
+/// Produces an order over the items of a blocked dataset that is close to random while only
+/// reading a few blocks at a time.
+///
+/// The blocks are shuffled, then grouped into windows of `window` blocks, and the items of each
+/// window are shuffled among themselves.
+#[derive(Debug, Clone)]
+pub struct BlockShuffleSampler {
+    blocks: Vec<Range<usize>>,
+    window: usize,
+    seed: u64,
+}
+
+/// The order of one epoch, along with the blocks each part of it reads.
+#[derive(Debug, Clone)]
+pub struct EpochPlan {
+    pub order: Vec<usize>,
+    /// The position in `order` each window starts at, and the blocks it reads
+    pub windows: Vec<(usize, Vec<usize>)>,
+}
+
+impl EpochPlan {
+    /// The index of the window that the item at `position` in `order` belongs to.
+    pub fn window_of(&self, position: usize) -> usize {
+        self.windows
+            .partition_point(|(start, _)| *start <= position)
+            .saturating_sub(1)
+    }
+}
+
+impl BlockShuffleSampler {
+    pub fn new(blocks: Vec<Range<usize>>, window: usize, seed: u64) -> Self {
+        Self {
+            blocks,
+            window: window.max(1),
+            seed,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.iter().map(|block| block.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The order of item indices for the given epoch. Every epoch is shuffled differently.
+    pub fn order(&self, epoch: usize) -> Vec<usize> {
+        self.plan(epoch).order
+    }
+
+    pub fn plan(&self, epoch: usize) -> EpochPlan {
+        let mut rng = SmallRng::seed_from_u64(self.seed.wrapping_add(epoch as u64));
+        let mut blocks: Vec<_> = (0..self.blocks.len()).collect();
+        blocks.shuffle(&mut rng);
+
+        let mut order = Vec::with_capacity(self.len());
+        let mut windows = Vec::with_capacity(blocks.len().div_ceil(self.window));
+        for window in blocks.chunks(self.window) {
+            let start = order.len();
+            order.extend(window.iter().flat_map(|block| self.blocks[*block].clone()));
+            order[start..].shuffle(&mut rng);
+            windows.push((start, window.to_vec()));
+        }
+        EpochPlan { order, windows }
+    }
+}
+
+/// The state shared between a [`BlockShuffledDataset`] and its prefetch thread.
+struct Shared<T> {
+    dataset: Arc<AcademyDataset<T>>,
+    sampler: BlockShuffleSampler,
+    current: RwLock<(usize, Arc<EpochPlan>)>,
+}
+
+impl<T> Shared<T> {
+    fn plan(&self, epoch: usize) -> Arc<EpochPlan> {
+        {
+            let current = self.current.read().unwrap();
+            if current.0 == epoch {
+                return current.1.clone();
+            }
+        }
+        let mut current = self.current.write().unwrap();
+        if current.0 != epoch {
+            *current = (epoch, self.sampler.plan(epoch).into());
+        }
+        current.1.clone()
+    }
+}
+
+struct Prefetcher {
+    /// The epoch and position of every read
+    positions: SyncSender<(usize, usize)>,
+    handle: JoinHandle<()>,
+}
+
+impl Prefetcher {
+    fn stop(self) {
+        drop(self.positions);
+        self.handle.join().ok();
+    }
+}
+
+/// An [`AcademyDataset`] read in the order given by a [`BlockShuffleSampler`].
+///
+/// A new order is drawn every time all items have been read, so every epoch is shuffled
+/// differently. This replaces `DataLoaderBuilder::shuffle`, which should not be used on top of
+/// it. Note that each dataloader worker reads its own window, so about `window * num_workers`
+/// blocks should fit in the memory budget of the dataset.
+pub struct BlockShuffledDataset<T> {
+    shared: Arc<Shared<T>>,
+    gets: AtomicUsize,
+    prefetcher: Option<Prefetcher>,
+}
+
+impl<T> BlockShuffledDataset<T> {
+    pub fn new(dataset: impl Into<Arc<AcademyDataset<T>>>, window: usize, seed: u64) -> Self {
+        let dataset = dataset.into();
+        let sampler = BlockShuffleSampler::new(
+            (0..dataset.block_count())
+                .map(|block| dataset.block_range(block))
+                .collect(),
+            window,
+            seed,
+        );
+        let plan = sampler.plan(0).into();
+        Self {
+            shared: Arc::new(Shared {
+                dataset,
+                sampler,
+                current: RwLock::new((0, plan)),
+            }),
+            gets: AtomicUsize::new(0),
+            prefetcher: None,
+        }
+    }
+
+    pub fn dataset(&self) -> &Arc<AcademyDataset<T>> {
+        &self.shared.dataset
+    }
+}
+
+impl<T: DeserializeOwned + Send + Sync + 'static> BlockShuffledDataset<T> {
+    /// Starts a thread that loads the blocks of the window being read, and the next
+    /// `blocks_ahead` blocks after it, before they are needed.
+    ///
+    /// Prefetched blocks count against the memory budget of the dataset, so it should fit the
+    /// windows being read plus `blocks_ahead` more blocks.
+    pub fn with_prefetch(mut self, blocks_ahead: usize) -> Self {
+        if let Some(prefetcher) = self.prefetcher.take() {
+            prefetcher.stop();
+        }
+        let (positions, receiver) = sync_channel::<(usize, usize)>(256);
+        let shared = self.shared.clone();
+
+        let handle = std::thread::spawn(move || {
+            let mut handled = (usize::MAX, vec![]);
+            while let Ok((epoch, position)) = receiver.recv() {
+                let plan = shared.plan(epoch);
+                if handled.0 != epoch {
+                    handled = (epoch, vec![false; plan.windows.len()]);
+                }
+                let window = plan.window_of(position);
+                if std::mem::replace(&mut handled.1[window], true) {
+                    continue;
+                }
+
+                let upcoming = plan.windows[window + 1..]
+                    .iter()
+                    .flat_map(|(_, blocks)| blocks)
+                    .take(blocks_ahead);
+                for block in plan.windows[window].1.iter().chain(upcoming) {
+                    shared.dataset.prefetch(*block);
+                }
+            }
+        });
+
+        self.prefetcher = Some(Prefetcher { positions, handle });
+        self
+    }
+}
+
+impl<T> Drop for BlockShuffledDataset<T> {
+    fn drop(&mut self) {
+        if let Some(prefetcher) = self.prefetcher.take() {
+            prefetcher.stop();
+        }
+    }
+}
+
+impl<T: DeserializeOwned + Send + Sync + Clone> Dataset<T> for BlockShuffledDataset<T> {
+    fn get(&self, index: usize) -> Option<T> {
+        let len = self.shared.dataset.len();
+        if index >= len {
+            return None;
+        }
+        let epoch = self.gets.fetch_add(1, Ordering::Relaxed) / len;
+        if let Some(prefetcher) = &self.prefetcher {
+            // The prefetcher only needs one position per window, so positions sent while it is
+            // busy can be dropped
+            prefetcher.positions.try_send((epoch, index)).ok();
+        }
+        let plan = self.shared.plan(epoch);
+        self.shared.dataset.get(plan.order[index])
+    }
+
+    fn len(&self) -> usize {
+        self.shared.dataset.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use burn::data::dataset::Dataset;
+    use tempfile::tempdir;
+
+    use super::{BlockShuffleSampler, BlockShuffledDataset};
+    use crate::data::{create_dataset, AcademyDataset, DataGen, DataGenerator};
+
+    #[test]
+    fn sampler_order_01() {
+        let blocks = vec![0..10, 10..20, 20..30, 30..40, 40..45];
+        let sampler = BlockShuffleSampler::new(blocks, 2, 8000);
+        let order = sampler.order(0);
+
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..45).collect::<Vec<_>>());
+        assert_eq!(order, sampler.order(0));
+        assert_ne!(order, sampler.order(1));
+    }
+
+    #[test]
+    fn sampler_windows_01() {
+        let blocks = (0..8).map(|i| i * 10..(i + 1) * 10).collect();
+        let sampler = BlockShuffleSampler::new(blocks, 2, 8000);
+        let plan = sampler.plan(0);
+        for (i, window) in plan.order.chunks(20).enumerate() {
+            let window_blocks: HashSet<_> = window.iter().map(|i| i / 10).collect();
+            assert_eq!(window_blocks.len(), 2, "{window_blocks:?}");
+            assert_eq!(plan.windows[i].0, i * 20);
+            assert_eq!(plan.window_of(i * 20 + 5), i);
+        }
+    }
+
+    struct ZeroGen;
+
+    impl DataGen for ZeroGen {
+        type Output = u32;
+
+        fn gen(&self) -> Self::Output {
+            0
+        }
+
+        fn skip(&mut self, _num: usize) {}
+    }
+
+    #[test]
+    fn prefetch_01() {
+        let dir = tempdir().unwrap();
+        create_dataset(
+            400,
+            dir.path().into(),
+            40,
+            DataGenerator::Immut(&mut ZeroGen),
+        );
+        let dataset =
+            BlockShuffledDataset::new(AcademyDataset::<u32>::new(dir.path().into(), 1000), 2, 8000)
+                .with_prefetch(2);
+
+        for _ in 0..2 {
+            for i in 0..dataset.len() {
+                assert_eq!(dataset.get(i), Some(0));
+            }
+        }
+        let stats = dataset.dataset().cache_stats();
+        assert_eq!(
+            stats.hits + stats.misses + stats.stalls,
+            2 * dataset.len() as u64
+        );
+        assert!(dataset.dataset().memory_usage() <= dataset.dataset().memory_limit());
+    }
+}
@@ -0,0 +1,197 @@
+use std::path::Path;
+
+use burn::data::dataset::Dataset;
+use serde::{Deserialize, Serialize};
+
+use super::{BlockWriter, Compression, DatasetError, RegressionItem};
+
+/// How [`WindowedSeriesDataset`] cuts a series into items.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowConfig {
+    /// The number of consecutive steps in each input
+    pub window: usize,
+    /// The number of steps between the starts of consecutive windows
+    pub stride: usize,
+    /// The number of consecutive steps whose targets make up the targets of an item
+    pub target_steps: usize,
+    /// How many steps after the last step of the window the targets start, like the `horizon` of
+    /// [`CsvImportOptions`](super::CsvImportOptions). 0 uses the targets of the last step of the
+    /// window itself.
+    pub target_offset: usize,
+}
+
+impl WindowConfig {
+    /// Windows at every step, each predicting the targets of the step after it.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            stride: 1,
+            target_steps: 1,
+            target_offset: 1,
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_target_steps(mut self, target_steps: usize) -> Self {
+        self.target_steps = target_steps;
+        self
+    }
+
+    pub fn with_target_offset(mut self, target_offset: usize) -> Self {
+        self.target_offset = target_offset;
+        self
+    }
+
+    /// The number of steps each item reads, from the first step of its window to its last target.
+    fn span(&self) -> usize {
+        self.window + self.target_offset + self.target_steps - 1
+    }
+}
+
+/// Writes a series of steps into a dataset at `data_path` that [`WindowedSeriesDataset`] can
+/// read, replacing any dataset already there. Each step is stored once, as an item with a single
+/// row of features.
+pub fn create_series_dataset(
+    data_path: &Path,
+    block_memory_size: usize,
+    compression: Compression,
+    steps: impl IntoIterator<Item = (Vec<f32>, Vec<f32>)>,
+) -> Result<usize, DatasetError> {
+    let mut writer = BlockWriter::create(data_path, block_memory_size, compression)?;
+    for (features, targets) in steps {
+        writer.push(RegressionItem {
+            input_shape: [1, features.len()],
+            inputs: features,
+            targets,
+            weight: None,
+        })?;
+    }
+    Ok(writer.finish()?.length)
+}
+
+/// Windows over a series of steps, such as one written by [`create_series_dataset`] or imported
+/// from a CSV file one row per item, that are only put together as they are read.
+///
+/// Each item has the features of `window` steps as its inputs, and the targets of `target_steps`
+/// steps, one after the other, as its targets. It takes the weight of the first of those steps
+/// that has one. Several series can be combined with [`ConcatDataset`](super::ConcatDataset)
+/// without windows crossing from one into the next.
+pub struct WindowedSeriesDataset<D> {
+    series: D,
+    config: WindowConfig,
+    length: usize,
+}
+
+impl<D: Dataset<RegressionItem>> WindowedSeriesDataset<D> {
+    pub fn new(series: D, config: WindowConfig) -> Self {
+        assert!(
+            config.window > 0 && config.stride > 0 && config.target_steps > 0,
+            "Windows, strides and target steps should be at least one step"
+        );
+        let length = series
+            .len()
+            .checked_sub(config.span())
+            .map(|free| free / config.stride + 1)
+            .unwrap_or_default();
+        Self {
+            series,
+            config,
+            length,
+        }
+    }
+
+    pub fn config(&self) -> &WindowConfig {
+        &self.config
+    }
+
+    pub fn series(&self) -> &D {
+        &self.series
+    }
+}
+
+impl<D: Dataset<RegressionItem>> Dataset<RegressionItem> for WindowedSeriesDataset<D> {
+    fn get(&self, index: usize) -> Option<RegressionItem> {
+        if index >= self.length {
+            return None;
+        }
+        let start = index * self.config.stride;
+        let mut inputs = vec![];
+        let mut features = 0;
+        for step in start..start + self.config.window {
+            let step = self.series.get(step)?;
+            features = step.inputs.len();
+            inputs.extend(step.inputs);
+        }
+
+        let target_start = start + self.config.window - 1 + self.config.target_offset;
+        let mut targets = vec![];
+        let mut weight = None;
+        for step in target_start..target_start + self.config.target_steps {
+            let step = self.series.get(step)?;
+            weight = weight.or(step.weight);
+            targets.extend(step.targets);
+        }
+        Some(RegressionItem {
+            inputs,
+            input_shape: [self.config.window, features],
+            targets,
+            weight,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use burn::data::dataset::Dataset;
+    use tempfile::tempdir;
+
+    use super::{create_series_dataset, WindowConfig, WindowedSeriesDataset};
+    use crate::data::{AcademyDataset, Compression, RegressionItem};
+
+    #[test]
+    fn windows_01() {
+        let dir = tempdir().unwrap();
+        let steps = (0..20).map(|i| (vec![i as f32, -(i as f32)], vec![i as f32 * 10.0]));
+        assert_eq!(
+            create_series_dataset(dir.path(), 100, Compression::None, steps).unwrap(),
+            20
+        );
+        let series = Arc::new(AcademyDataset::<RegressionItem>::new(
+            dir.path().into(),
+            1 << 20,
+        ));
+
+        // Each item reads 4 + 1 + 2 - 1 = 6 steps, starting every 2 steps
+        let windows = WindowedSeriesDataset::new(
+            series.clone(),
+            WindowConfig::new(4).with_stride(2).with_target_steps(2),
+        );
+        assert_eq!(windows.len(), 8);
+        assert_eq!(
+            windows.get(1).unwrap(),
+            RegressionItem {
+                inputs: vec![2.0, -2.0, 3.0, -3.0, 4.0, -4.0, 5.0, -5.0],
+                input_shape: [4, 2],
+                targets: vec![60.0, 70.0],
+                weight: None,
+            }
+        );
+        assert_eq!(windows.get(7).unwrap().targets, [180.0, 190.0]);
+        assert!(windows.get(8).is_none());
+
+        let same_step =
+            WindowedSeriesDataset::new(series, WindowConfig::new(20).with_target_offset(0));
+        assert_eq!(same_step.len(), 1);
+        assert_eq!(same_step.get(0).unwrap().targets, [190.0]);
+    }
+}
@@ -0,0 +1,620 @@
+use std::{collections::HashMap, hash::Hash, ops::Range, path::Path};
+
+use burn::data::dataset::Dataset;
+use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    format::{fnv1a, FNV_OFFSET},
+    AcademyDataset, DatasetError, SubsetDataset,
+};
+
+const SPLIT_MAGIC: [u8; 8] = *b"MACSPLIT";
+const SPLIT_VERSION: u32 = 1;
+
+/// How to split a dataset into training, validation and testing partitions.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SplitConfig {
+    /// The relative sizes of the train, valid and test partitions. They do not need to sum to 1.
+    pub ratios: [f64; 3],
+    pub seed: u64,
+    /// Assign whole blocks to each partition, so that reading a partition only loads its own
+    /// blocks. The partitions then only approximately match the ratios.
+    pub block_aligned: bool,
+}
+
+impl SplitConfig {
+    pub fn new(train: f64, valid: f64, test: f64) -> Self {
+        Self {
+            ratios: [train, valid, test],
+            seed: 8000,
+            block_aligned: false,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_block_aligned(mut self, block_aligned: bool) -> Self {
+        self.block_aligned = block_aligned;
+        self
+    }
+
+    /// The number of items each partition should get out of `length`.
+    fn targets(&self, length: usize) -> [usize; 3] {
+        assert!(
+            self.ratios.iter().all(|ratio| *ratio >= 0.0) && self.ratios.iter().sum::<f64>() > 0.0,
+            "Split ratios should be non-negative and not all zero"
+        );
+        let total: f64 = self.ratios.iter().sum();
+        let train = (length as f64 * self.ratios[0] / total).round() as usize;
+        let valid = ((length as f64 * self.ratios[1] / total).round() as usize).min(length - train);
+        [train, valid, length - train - valid]
+    }
+}
+
+/// How the items of a [`DatasetSplit`] were assigned to its partitions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitKind {
+    /// Each item, or each block if block aligned, on its own
+    #[default]
+    Random,
+    /// Whole groups of items with the same key, so no group is on more than one side
+    Grouped,
+    /// The items of each stratum of items with the same key split on their own, so every
+    /// partition has about the same share of each stratum
+    Stratified,
+}
+
+/// Groups the indices of `keys` by their key, in the order each key first appears.
+fn group_indices<K: Hash + Eq>(keys: Vec<K>) -> Vec<Vec<usize>> {
+    let mut positions = HashMap::new();
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for (index, key) in keys.into_iter().enumerate() {
+        let position = *positions.entry(key).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+        groups[position].push(index);
+    }
+    groups
+}
+
+/// A hash of which group each item of `groups` is in, so that keys that group the items the
+/// same way hash the same.
+fn groups_hash(groups: &[Vec<usize>]) -> u64 {
+    let mut assignment = vec![0u64; groups.iter().map(Vec::len).sum()];
+    for (group, indices) in groups.iter().enumerate() {
+        for index in indices {
+            assignment[*index] = group as u64;
+        }
+    }
+    assignment
+        .iter()
+        .fold(FNV_OFFSET, |hash, group| fnv1a(hash, &group.to_le_bytes()))
+}
+
+/// The key of every item of `dataset`, in order, reading its blocks in parallel.
+fn dataset_keys<T, K>(dataset: &AcademyDataset<T>, key: impl Fn(&T) -> K + Sync) -> Vec<K>
+where
+    T: DeserializeOwned + Send + Sync + Clone,
+    K: Send,
+{
+    let blocks: Vec<Vec<K>> = (0..dataset.block_count())
+        .into_par_iter()
+        .map(|block| {
+            dataset
+                .block_range(block)
+                .map(|i| key(&dataset.get(i).expect("Items in range should be readable")))
+                .collect()
+        })
+        .collect();
+    blocks.into_iter().flatten().collect()
+}
+
+/// A seeded partition of the indices of a dataset into training, validation and testing indices.
+///
+/// Each partition is sorted, so reading it in order reads the dataset in order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DatasetSplit {
+    pub config: SplitConfig,
+    #[serde(default)]
+    pub kind: SplitKind,
+    /// The hash of the groups or strata of a grouped or stratified split
+    #[serde(default)]
+    pub keys_hash: Option<u64>,
+    pub dataset_length: usize,
+    /// The [config hash](AcademyDataset::config_hash) of the dataset the split was made for, if
+    /// it was made from an [`AcademyDataset`]
+    #[serde(default)]
+    pub config_hash: Option<u64>,
+    pub train: Vec<usize>,
+    pub valid: Vec<usize>,
+    pub test: Vec<usize>,
+}
+
+impl DatasetSplit {
+    pub fn new<T>(dataset: &AcademyDataset<T>, config: SplitConfig) -> Self {
+        Self {
+            config_hash: Some(dataset.config_hash()),
+            ..Self::from_blocks(
+                (0..dataset.block_count())
+                    .map(|block| dataset.block_range(block))
+                    .collect(),
+                config,
+            )
+        }
+    }
+
+    /// Splits the indices covered by `blocks`, which should be contiguous and start at 0.
+    pub fn from_blocks(blocks: Vec<Range<usize>>, config: SplitConfig) -> Self {
+        let length = blocks.last().map(|block| block.end).unwrap_or_default();
+        let targets = config.targets(length);
+        let mut rng = SmallRng::seed_from_u64(config.seed);
+        let mut partitions: [Vec<usize>; 3] = Default::default();
+
+        if config.block_aligned {
+            partitions = Self::assign_whole(blocks, targets, &mut rng);
+        } else {
+            let mut indices: Vec<_> = (0..length).collect();
+            indices.shuffle(&mut rng);
+            let mut rest = indices.as_slice();
+            for (partition, target) in partitions.iter_mut().zip(targets) {
+                let (taken, remaining) = rest.split_at(target);
+                partition.extend_from_slice(taken);
+                rest = remaining;
+            }
+        }
+
+        Self::from_partitions(config, SplitKind::Random, None, length, partitions)
+    }
+
+    /// Splits `dataset` so that all the items with the same `key`, such as the simulation run or
+    /// customer they came from, end up in the same partition. The partitions then only
+    /// approximately match the ratios, and `block_aligned` is ignored.
+    pub fn grouped<T, K>(
+        dataset: &AcademyDataset<T>,
+        config: SplitConfig,
+        key: impl Fn(&T) -> K + Sync,
+    ) -> Self
+    where
+        T: DeserializeOwned + Send + Sync + Clone,
+        K: Hash + Eq + Send,
+    {
+        Self {
+            config_hash: Some(dataset.config_hash()),
+            ..Self::from_groups(group_indices(dataset_keys(dataset, key)), config)
+        }
+    }
+
+    /// Splits the indices `0..n` held by `groups`, keeping each group in one partition.
+    pub fn from_groups(groups: Vec<Vec<usize>>, config: SplitConfig) -> Self {
+        let length = groups.iter().map(Vec::len).sum();
+        let targets = config.targets(length);
+        let mut rng = SmallRng::seed_from_u64(config.seed);
+        let keys_hash = groups_hash(&groups);
+        let partitions = Self::assign_whole(groups, targets, &mut rng);
+        Self::from_partitions(
+            config,
+            SplitKind::Grouped,
+            Some(keys_hash),
+            length,
+            partitions,
+        )
+    }
+
+    /// Splits `dataset` so that each partition has about the same share of the items of every
+    /// `key`, such as a bucket of the targets. Keys have to be hashable, so continuous targets
+    /// should be bucketed first. `block_aligned` is ignored.
+    pub fn stratified<T, K>(
+        dataset: &AcademyDataset<T>,
+        config: SplitConfig,
+        key: impl Fn(&T) -> K + Sync,
+    ) -> Self
+    where
+        T: DeserializeOwned + Send + Sync + Clone,
+        K: Hash + Eq + Send,
+    {
+        Self {
+            config_hash: Some(dataset.config_hash()),
+            ..Self::from_strata(group_indices(dataset_keys(dataset, key)), config)
+        }
+    }
+
+    /// Splits the indices `0..n` held by `strata`, splitting each stratum by the ratios.
+    pub fn from_strata(strata: Vec<Vec<usize>>, config: SplitConfig) -> Self {
+        let length = strata.iter().map(Vec::len).sum();
+        let keys_hash = groups_hash(&strata);
+        let mut rng = SmallRng::seed_from_u64(config.seed);
+        let mut partitions: [Vec<usize>; 3] = Default::default();
+        for mut stratum in strata {
+            let targets = config.targets(stratum.len());
+            stratum.shuffle(&mut rng);
+            let mut rest = stratum.as_slice();
+            for (partition, target) in partitions.iter_mut().zip(targets) {
+                let (taken, remaining) = rest.split_at(target);
+                partition.extend_from_slice(taken);
+                rest = remaining;
+            }
+        }
+        Self::from_partitions(
+            config,
+            SplitKind::Stratified,
+            Some(keys_hash),
+            length,
+            partitions,
+        )
+    }
+
+    /// Shuffles `units` and gives each whole unit to the first partition that is still short of
+    /// its target, or to train once none are, so a partition with a ratio of 0 stays empty.
+    fn assign_whole<U: IntoIterator<Item = usize>>(
+        mut units: Vec<U>,
+        targets: [usize; 3],
+        rng: &mut SmallRng,
+    ) -> [Vec<usize>; 3] {
+        let mut partitions: [Vec<usize>; 3] = Default::default();
+        units.shuffle(rng);
+        for unit in units {
+            let partition = (0..3)
+                .find(|i| partitions[*i].len() < targets[*i])
+                .unwrap_or(0);
+            partitions[partition].extend(unit);
+        }
+        partitions
+    }
+
+    fn from_partitions(
+        config: SplitConfig,
+        kind: SplitKind,
+        keys_hash: Option<u64>,
+        dataset_length: usize,
+        partitions: [Vec<usize>; 3],
+    ) -> Self {
+        let [mut train, mut valid, mut test] = partitions;
+        train.sort_unstable();
+        valid.sort_unstable();
+        test.sort_unstable();
+        Self {
+            config,
+            kind,
+            keys_hash,
+            dataset_length,
+            config_hash: None,
+            train,
+            valid,
+            test,
+        }
+    }
+
+    /// Loads a split saved with [`DatasetSplit::save`]. Splits saved before splits had a kind are
+    /// read as random splits.
+    pub fn load(path: &Path) -> Result<Self, DatasetError> {
+        let bytes = std::fs::read(path)?;
+        let Some(rest) = bytes.strip_prefix(&SPLIT_MAGIC) else {
+            log::warn!(
+                "Split at {} predates split kinds and is read as a random split",
+                path.display()
+            );
+            return Ok(bincode::deserialize::<LegacySplit>(&bytes)?.into());
+        };
+        let version: u32 = bincode::deserialize(rest)?;
+        if version != SPLIT_VERSION {
+            return Err(DatasetError::Malformed(format!(
+                "split version {version} is not supported"
+            )));
+        }
+        Ok(bincode::deserialize(&rest[4..])?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DatasetError> {
+        let mut bytes = SPLIT_MAGIC.to_vec();
+        bytes.extend(bincode::serialize(&(SPLIT_VERSION, self))?);
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Loads the split saved at `path`, or creates and saves a new one if there is none, or if it
+    /// was made with a different config or for a dataset with a different length or config
+    /// hash.
+    pub fn load_or_create<T>(
+        dataset: &AcademyDataset<T>,
+        path: &Path,
+        config: SplitConfig,
+    ) -> Result<Self, DatasetError> {
+        Self::load_or_create_with(path, config, SplitKind::Random, None, dataset, |config| {
+            Self::new(dataset, config)
+        })
+    }
+
+    /// Like [`DatasetSplit::load_or_create`] for [`DatasetSplit::grouped`]. The split is also
+    /// recreated if the keys group the items differently than when it was made, so every key is
+    /// read even when the saved split is reused.
+    pub fn load_or_create_grouped<T, K>(
+        dataset: &AcademyDataset<T>,
+        path: &Path,
+        config: SplitConfig,
+        key: impl Fn(&T) -> K + Sync,
+    ) -> Result<Self, DatasetError>
+    where
+        T: DeserializeOwned + Send + Sync + Clone,
+        K: Hash + Eq + Send,
+    {
+        let groups = group_indices(dataset_keys(dataset, key));
+        Self::load_or_create_with(
+            path,
+            config,
+            SplitKind::Grouped,
+            Some(groups_hash(&groups)),
+            dataset,
+            |config| Self::from_groups(groups, config),
+        )
+    }
+
+    /// Like [`DatasetSplit::load_or_create`] for [`DatasetSplit::stratified`]. The split is also
+    /// recreated if the keys stratify the items differently than when it was made.
+    pub fn load_or_create_stratified<T, K>(
+        dataset: &AcademyDataset<T>,
+        path: &Path,
+        config: SplitConfig,
+        key: impl Fn(&T) -> K + Sync,
+    ) -> Result<Self, DatasetError>
+    where
+        T: DeserializeOwned + Send + Sync + Clone,
+        K: Hash + Eq + Send,
+    {
+        let strata = group_indices(dataset_keys(dataset, key));
+        Self::load_or_create_with(
+            path,
+            config,
+            SplitKind::Stratified,
+            Some(groups_hash(&strata)),
+            dataset,
+            |config| Self::from_strata(strata, config),
+        )
+    }
+
+    fn load_or_create_with<T>(
+        path: &Path,
+        config: SplitConfig,
+        kind: SplitKind,
+        keys_hash: Option<u64>,
+        dataset: &AcademyDataset<T>,
+        create: impl FnOnce(SplitConfig) -> Self,
+    ) -> Result<Self, DatasetError> {
+        if path.exists() {
+            match Self::load(path) {
+                Ok(split)
+                    if split.config == config
+                        && split.kind == kind
+                        && split.keys_hash == keys_hash
+                        && split.dataset_length == dataset.length
+                        && split.config_hash == Some(dataset.config_hash()) =>
+                {
+                    return Ok(split)
+                }
+                Ok(_) => log::warn!(
+                    "Split at {} does not match the dataset, keys or split config and will be recreated",
+                    path.display()
+                ),
+                Err(e) => log::warn!("{e}, so the split at {} will be recreated", path.display()),
+            }
+        }
+        let split = Self {
+            config_hash: Some(dataset.config_hash()),
+            ..create(config)
+        };
+        split.save(path)?;
+        Ok(split)
+    }
+
+    /// Views of `dataset` restricted to the train, valid and test partitions.
+    pub fn subsets<D: Clone>(
+        &self,
+        dataset: D,
+    ) -> (SubsetDataset<D>, SubsetDataset<D>, SubsetDataset<D>) {
+        (
+            SubsetDataset::new(dataset.clone(), self.train.clone()),
+            SubsetDataset::new(dataset.clone(), self.valid.clone()),
+            SubsetDataset::new(dataset, self.test.clone()),
+        )
+    }
+}
+
+/// The layout of splits saved before splits had a kind, which were all random.
+#[derive(Deserialize)]
+struct LegacySplit {
+    config: SplitConfig,
+    dataset_length: usize,
+    train: Vec<usize>,
+    valid: Vec<usize>,
+    test: Vec<usize>,
+}
+
+impl From<LegacySplit> for DatasetSplit {
+    fn from(legacy: LegacySplit) -> Self {
+        Self {
+            config: legacy.config,
+            kind: SplitKind::Random,
+            keys_hash: None,
+            dataset_length: legacy.dataset_length,
+            config_hash: None,
+            train: legacy.train,
+            valid: legacy.valid,
+            test: legacy.test,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::{DatasetSplit, SplitConfig, SplitKind};
+    use crate::data::{create_dataset, AcademyDataset, DataGenerator, IndexedDataGen};
+
+    #[test]
+    fn split_01() {
+        let blocks: Vec<_> = (0..10).map(|i| i * 10..(i + 1) * 10).collect();
+        let split = DatasetSplit::from_blocks(blocks.clone(), SplitConfig::new(0.8, 0.1, 0.1));
+        assert_eq!(
+            (split.train.len(), split.valid.len(), split.test.len()),
+            (80, 10, 10)
+        );
+        let mut all: Vec<_> = [&split.train, &split.valid, &split.test]
+            .into_iter()
+            .flatten()
+            .copied()
+            .collect();
+        all.sort_unstable();
+        assert_eq!(all, (0..100).collect::<Vec<_>>());
+        assert_eq!(
+            split,
+            DatasetSplit::from_blocks(blocks.clone(), SplitConfig::new(0.8, 0.1, 0.1))
+        );
+
+        let aligned = DatasetSplit::from_blocks(
+            blocks,
+            SplitConfig::new(0.8, 0.1, 0.1).with_block_aligned(true),
+        );
+        for partition in [&aligned.train, &aligned.valid, &aligned.test] {
+            assert_eq!(partition.len() % 10, 0);
+            for block in partition.chunks(10) {
+                assert_eq!(block[0] % 10, 0);
+                assert_eq!(block[9], block[0] + 9);
+            }
+        }
+    }
+
+    /// Items `0..100` in 20 runs of 5
+    struct RunGen;
+
+    impl IndexedDataGen for RunGen {
+        type Output = (u32, u32);
+
+        fn gen_at(&self, index: usize, _seed: u64) -> Self::Output {
+            (index as u32 / 5, index as u32)
+        }
+    }
+
+    #[test]
+    fn split_grouped_01() {
+        let dir = tempdir().unwrap();
+        create_dataset(
+            100,
+            dir.path().into(),
+            100,
+            DataGenerator::Indexed(&RunGen, 0),
+        );
+        let db = AcademyDataset::<(u32, u32)>::new(dir.path().into(), 1 << 20);
+        let config = SplitConfig::new(0.6, 0.2, 0.2);
+
+        let grouped = DatasetSplit::grouped(&db, config.clone(), |(run, _)| *run);
+        assert_eq!(grouped.kind, SplitKind::Grouped);
+        assert_eq!(
+            (grouped.train.len(), grouped.valid.len(), grouped.test.len()),
+            (60, 20, 20)
+        );
+        for partition in [&grouped.train, &grouped.valid, &grouped.test] {
+            for run in partition.chunks(5) {
+                assert_eq!(run[0] % 5, 0);
+                assert_eq!(run[4], run[0] + 4);
+            }
+        }
+
+        let path = dir.path().join("split.dat");
+        let saved =
+            DatasetSplit::load_or_create_grouped(&db, &path, config.clone(), |(run, _)| *run)
+                .unwrap();
+        assert_eq!(saved, grouped);
+        // Grouping the runs in pairs recreates the split
+        let pairs =
+            DatasetSplit::load_or_create_grouped(&db, &path, config.clone(), |(run, _)| run / 2)
+                .unwrap();
+        assert_ne!(pairs.keys_hash, grouped.keys_hash);
+        for partition in [&pairs.train, &pairs.valid, &pairs.test] {
+            for pair in partition.chunks(10) {
+                assert_eq!(pair[9], pair[0] + 9);
+            }
+        }
+        assert_eq!(DatasetSplit::load(&path).unwrap(), pairs);
+
+        // Strata of 50, 30 and 20 items
+        let bucket = |(_, i): &(u32, u32)| (*i >= 50) as u8 + (*i >= 80) as u8;
+        let stratified =
+            DatasetSplit::load_or_create_stratified(&db, &path, config.clone(), bucket).unwrap();
+        let count = |partition: &[usize], stratum: u8| {
+            partition
+                .iter()
+                .filter(|i| bucket(&(0, **i as u32)) == stratum)
+                .count()
+        };
+        assert_eq!(
+            [0, 1, 2].map(|stratum| count(&stratified.valid, stratum)),
+            [10, 6, 4]
+        );
+        assert_eq!(
+            DatasetSplit::load_or_create_stratified(&db, &path, config.clone(), bucket).unwrap(),
+            stratified
+        );
+        // A random split of the same config replaces the stratified one
+        let random = DatasetSplit::load_or_create(&db, &path, config.clone()).unwrap();
+        assert_eq!(random.kind, SplitKind::Random);
+        assert_eq!(random.config_hash, Some(db.config_hash()));
+        assert_eq!(DatasetSplit::load(&path).unwrap(), random);
+
+        // Regenerating the dataset with the same length recreates the split
+        create_dataset(
+            100,
+            dir.path().into(),
+            200,
+            DataGenerator::Indexed(&RunGen, 0),
+        );
+        let db = AcademyDataset::<(u32, u32)>::new(dir.path().into(), 1 << 20);
+        assert_ne!(Some(db.config_hash()), random.config_hash);
+        let regenerated = DatasetSplit::load_or_create(&db, &path, config).unwrap();
+        assert_eq!(regenerated.config_hash, Some(db.config_hash()));
+        assert_eq!(DatasetSplit::load(&path).unwrap(), regenerated);
+    }
+
+    #[test]
+    fn split_no_test_01() {
+        let config = SplitConfig::new(0.7, 0.3, 0.0).with_block_aligned(true);
+        let blocks = vec![0..30, 30..35, 35..60, 60..61, 61..100];
+        for seed in 0..20 {
+            let split = DatasetSplit::from_blocks(blocks.clone(), config.clone().with_seed(seed));
+            assert!(split.test.is_empty());
+            assert_eq!(split.train.len() + split.valid.len(), 100);
+        }
+
+        // Empty groups left over once every partition has its share go to train, not test
+        let groups = vec![(0..6).collect(), (6..10).collect(), vec![], vec![]];
+        for seed in 0..20 {
+            let split = DatasetSplit::from_groups(groups.clone(), config.clone().with_seed(seed));
+            assert!(split.test.is_empty());
+        }
+    }
+
+    #[test]
+    fn split_persist_01() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("split.dat");
+        let split = DatasetSplit::from_blocks(vec![0..25, 25..50], SplitConfig::new(0.5, 0.5, 0.0));
+        split.save(&path).unwrap();
+        assert_eq!(DatasetSplit::load(&path).unwrap(), split);
+
+        // Splits saved without a kind are read as random splits
+        let legacy = (
+            split.config.clone(),
+            split.dataset_length,
+            &split.train,
+            &split.valid,
+            &split.test,
+        );
+        std::fs::write(&path, bincode::serialize(&legacy).unwrap()).unwrap();
+        assert_eq!(DatasetSplit::load(&path).unwrap(), split);
+    }
+}
@@ -0,0 +1,344 @@
+use std::{ops::Range, path::Path};
+
+use burn::{
+    config::Config,
+    data::dataset::Dataset,
+    tensor::{backend::Backend, Tensor},
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{AcademyDataset, DatasetError};
+
+pub(crate) const STATS_FILE: &str = "stats.json";
+/// The number of items each task reads when a dataset has no blocks to split the work by
+const CHUNK_LEN: usize = 1024;
+
+/// The values of the inputs of `item`, its number of input features, and the values of its
+/// targets.
+pub(crate) fn item_values<B, I>(item: I) -> (Vec<f32>, usize, Vec<f32>)
+where
+    B: Backend,
+    I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+{
+    let (input, target) = item.into();
+    let [_, features] = input.dims();
+    (
+        input.into_data().convert::<f32>().value,
+        features,
+        target.into_data().convert::<f32>().value,
+    )
+}
+
+/// Summary statistics of one input feature or target over a whole dataset.
+///
+/// NaNs are counted but otherwise left out. The other fields are 0 if every value was NaN.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeatureStats {
+    /// The number of values that are not NaN
+    pub count: u64,
+    pub nan_count: u64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// Running moments of a feature, which can be merged across blocks.
+#[derive(Debug, Clone, Copy)]
+struct Moments {
+    count: u64,
+    nan_count: u64,
+    mean: f64,
+    /// The sum of squared differences from the mean
+    m2: f64,
+    min: f32,
+    max: f32,
+}
+
+impl Default for Moments {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            nan_count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        }
+    }
+}
+
+impl Moments {
+    fn push(&mut self, x: f32) {
+        if x.is_nan() {
+            self.nan_count += 1;
+            return;
+        }
+        self.count += 1;
+        let delta = x as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x as f64 - self.mean);
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+    }
+
+    fn merge(self, other: Self) -> Self {
+        let count = self.count + other.count;
+        if count == 0 {
+            return Self {
+                nan_count: self.nan_count + other.nan_count,
+                ..self
+            };
+        }
+        let delta = other.mean - self.mean;
+        Self {
+            count,
+            nan_count: self.nan_count + other.nan_count,
+            mean: self.mean + delta * other.count as f64 / count as f64,
+            m2: self.m2
+                + other.m2
+                + delta * delta * self.count as f64 * other.count as f64 / count as f64,
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    fn stats(self) -> FeatureStats {
+        if self.count == 0 {
+            return FeatureStats {
+                count: 0,
+                nan_count: self.nan_count,
+                mean: 0.0,
+                std_dev: 0.0,
+                min: 0.0,
+                max: 0.0,
+            };
+        }
+        FeatureStats {
+            count: self.count,
+            nan_count: self.nan_count,
+            mean: self.mean,
+            std_dev: (self.m2 / self.count as f64).sqrt(),
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+/// Merges per-feature moments, extending the shorter side if items have different shapes.
+fn merge_all(mut a: Vec<Moments>, b: Vec<Moments>) -> Vec<Moments> {
+    if a.len() < b.len() {
+        a.resize(b.len(), Moments::default());
+    }
+    for (a, b) in a.iter_mut().zip(b) {
+        *a = a.merge(b);
+    }
+    a
+}
+
+/// Per-feature statistics of the inputs and targets of a dataset, saved as `stats.json` next to
+/// its `config.dat`.
+///
+/// Inputs are `[rows, features]` tensors, and each feature is summarized over every row of every
+/// item.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DatasetStats {
+    /// The length of the dataset the statistics were computed over
+    pub length: usize,
+    /// The [config hash](AcademyDataset::config_hash) of the dataset the statistics were computed
+    /// over, if it was an [`AcademyDataset`]
+    #[serde(default)]
+    pub config_hash: Option<u64>,
+    pub inputs: Vec<FeatureStats>,
+    pub targets: Vec<FeatureStats>,
+}
+
+impl Config for DatasetStats {}
+
+impl DatasetStats {
+    /// Computes the statistics of `dataset`, reading its blocks in parallel.
+    ///
+    /// Items are converted into tensors of the backend `B`, so a CPU backend is the best fit.
+    pub fn compute<B, I>(dataset: &AcademyDataset<I>) -> Self
+    where
+        B: Backend,
+        I: DeserializeOwned + Send + Sync + Clone + Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    {
+        let blocks = (0..dataset.block_count())
+            .map(|block| dataset.block_range(block))
+            .collect();
+        Self {
+            config_hash: Some(dataset.config_hash()),
+            ..Self::compute_ranges::<B, I, _>(dataset, blocks)
+        }
+    }
+
+    /// Like [`DatasetStats::compute`], for datasets that are not split into blocks, such as views.
+    pub fn compute_on<B, I, D>(dataset: &D) -> Self
+    where
+        B: Backend,
+        I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+        D: Dataset<I>,
+    {
+        let chunks = (0..dataset.len())
+            .step_by(CHUNK_LEN)
+            .map(|start| start..(start + CHUNK_LEN).min(dataset.len()))
+            .collect();
+        Self::compute_ranges::<B, I, D>(dataset, chunks)
+    }
+
+    fn compute_ranges<B, I, D>(dataset: &D, ranges: Vec<Range<usize>>) -> Self
+    where
+        B: Backend,
+        I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+        D: Dataset<I>,
+    {
+        let (inputs, targets) = ranges
+            .into_par_iter()
+            .map(|range| {
+                let mut inputs: Vec<Moments> = vec![];
+                let mut targets: Vec<Moments> = vec![];
+                for item in range.filter_map(|i| dataset.get(i)) {
+                    let (input, features, target) = item_values::<B, I>(item);
+                    if inputs.len() < features {
+                        inputs.resize(features, Moments::default());
+                    }
+                    for (j, x) in input.into_iter().enumerate() {
+                        inputs[j % features].push(x);
+                    }
+                    if targets.len() < target.len() {
+                        targets.resize(target.len(), Moments::default());
+                    }
+                    for (y, moments) in target.into_iter().zip(&mut targets) {
+                        moments.push(y);
+                    }
+                }
+                (inputs, targets)
+            })
+            .reduce(
+                || (vec![], vec![]),
+                |a, b| (merge_all(a.0, b.0), merge_all(a.1, b.1)),
+            );
+
+        Self {
+            length: dataset.len(),
+            config_hash: None,
+            inputs: inputs.into_iter().map(Moments::stats).collect(),
+            targets: targets.into_iter().map(Moments::stats).collect(),
+        }
+    }
+
+    /// Loads the statistics saved next to the dataset at `data_path`.
+    pub fn load_from(data_path: &Path) -> Result<Self, DatasetError> {
+        Self::load(data_path.join(STATS_FILE))
+            .map_err(|e| DatasetError::Malformed(format!("{STATS_FILE} could not be read: {e}")))
+    }
+
+    pub fn save_to(&self, data_path: &Path) -> Result<(), DatasetError> {
+        Ok(self.save(data_path.join(STATS_FILE))?)
+    }
+
+    /// Loads the statistics saved next to `dataset`, or computes and saves them if there are none
+    /// or they were computed over a dataset of a different length or config hash, such as one
+    /// written by a differently configured generator.
+    pub fn load_or_compute<B, I>(dataset: &AcademyDataset<I>) -> Result<Self, DatasetError>
+    where
+        B: Backend,
+        I: DeserializeOwned + Send + Sync + Clone + Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    {
+        let data_path = dataset.data_path();
+        if data_path.join(STATS_FILE).exists() {
+            match Self::load_from(data_path) {
+                Ok(stats)
+                    if stats.length == dataset.len()
+                        && stats.config_hash == Some(dataset.config_hash()) =>
+                {
+                    return Ok(stats)
+                }
+                Ok(_) => log::warn!(
+                    "Statistics at {} were computed over a different dataset and will be recomputed",
+                    data_path.display()
+                ),
+                Err(e) => log::warn!("{e}, so the statistics will be recomputed"),
+            }
+        }
+        let stats = Self::compute::<B, I>(dataset);
+        stats.save_to(data_path)?;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn_ndarray::NdArray;
+    use tempfile::tempdir;
+
+    use super::DatasetStats;
+    use crate::data::{
+        create_dataset, AcademyDataset, DataGenerator, IndexedDataGen, RegressionItem,
+    };
+
+    /// Ramps with targets offset by the given amount
+    struct RampGen(f32);
+
+    impl IndexedDataGen for RampGen {
+        type Output = RegressionItem;
+
+        fn gen_at(&self, index: usize, _seed: u64) -> Self::Output {
+            let x = index as f32;
+            RegressionItem {
+                inputs: vec![x, -x, x + 1.0, f32::NAN],
+                input_shape: [2, 2],
+                targets: vec![2.0 * x + self.0],
+                weight: None,
+            }
+        }
+
+        fn fingerprint(&self) -> Option<u64> {
+            Some(self.0.to_bits() as u64)
+        }
+    }
+
+    #[test]
+    fn stats_01() {
+        let dir = tempdir().unwrap();
+        create_dataset(
+            100,
+            dir.path().into(),
+            256,
+            DataGenerator::Indexed(&RampGen(0.0), 0),
+        );
+        let db = AcademyDataset::<RegressionItem>::new(dir.path().into(), 1 << 20);
+        assert!(db.block_count() > 1);
+
+        let stats = DatasetStats::load_or_compute::<NdArray, _>(&db).unwrap();
+        assert_eq!(stats.length, 100);
+        let [first, second] = &stats.inputs[..] else {
+            panic!("Inputs should have 2 features");
+        };
+        // The first feature takes the values 0..100 and 1..101
+        assert_eq!((first.count, first.nan_count), (200, 0));
+        assert!((first.mean - 50.0).abs() < 1e-9);
+        assert_eq!((first.min, first.max), (0.0, 100.0));
+        assert_eq!((second.count, second.nan_count), (100, 100));
+        assert_eq!((second.min, second.max), (-99.0, 0.0));
+        let target = &stats.targets[0];
+        assert!((target.mean - 99.0).abs() < 1e-9);
+        assert!((target.std_dev - 2.0 * (9999.0f64 / 12.0).sqrt()).abs() < 1e-6);
+
+        assert_eq!(DatasetStats::load_from(dir.path()).unwrap(), stats);
+
+        // Regenerating the dataset with another generator of the same length recomputes them
+        create_dataset(
+            100,
+            dir.path().into(),
+            256,
+            DataGenerator::Indexed(&RampGen(100.0), 0),
+        );
+        let db = AcademyDataset::<RegressionItem>::new(dir.path().into(), 1 << 20);
+        let offset = DatasetStats::load_or_compute::<NdArray, _>(&db).unwrap();
+        assert_eq!(offset.length, 100);
+        assert!((offset.targets[0].mean - 199.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use burn::data::{
+    dataloader::{DataLoader, DataLoaderIterator},
+    dataset::Dataset,
+};
+
+use super::{DataGen, IndexedDataGen, MutDataGen};
+
+enum Source<T> {
+    Immut(Box<dyn DataGen<Output = T> + Send>),
+    Mut(Mutex<Box<dyn MutDataGen<Output = T> + Send>>),
+    Indexed(Box<dyn IndexedDataGen<Output = T> + Send>, u64),
+}
+
+/// A dataset of `epoch_length` items that are generated as they are read, so every epoch sees
+/// fresh items and nothing is written to disk.
+///
+/// Reading an index twice gives different items, except from indexed generators within the same
+/// epoch, so it only suits training, where each item is read once per epoch. Validation should
+/// use a persisted dataset.
+pub struct StreamingDataset<T> {
+    source: Source<T>,
+    epoch_length: usize,
+    epoch: StreamEpoch,
+}
+
+/// The epoch a [`StreamingDataset`] is in, which tells indexed generators which items to
+/// generate. It only changes when it is set, so reading items does not move it.
+#[derive(Clone, Default)]
+pub struct StreamEpoch(Arc<AtomicUsize>);
+
+impl StreamEpoch {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, epoch: usize) {
+        self.0.store(epoch, Ordering::Relaxed);
+    }
+}
+
+impl<T> StreamingDataset<T> {
+    pub fn new(gen: impl DataGen<Output = T> + Send + 'static, epoch_length: usize) -> Self {
+        Self::with_source(Source::Immut(Box::new(gen)), epoch_length)
+    }
+
+    /// Generates items one at a time, as the generator can not be shared between the data loader
+    /// workers.
+    pub fn from_mut(
+        gen: impl MutDataGen<Output = T> + Send + 'static,
+        epoch_length: usize,
+    ) -> Self {
+        Self::with_source(Source::Mut(Mutex::new(Box::new(gen))), epoch_length)
+    }
+
+    /// Generates the items of epoch `e` at the indices `e * epoch_length..(e + 1) * epoch_length`,
+    /// so runs with the same seed see the same items.
+    pub fn indexed(
+        gen: impl IndexedDataGen<Output = T> + Send + 'static,
+        seed: u64,
+        epoch_length: usize,
+    ) -> Self {
+        Self::with_source(Source::Indexed(Box::new(gen), seed), epoch_length)
+    }
+
+    fn with_source(source: Source<T>, epoch_length: usize) -> Self {
+        Self {
+            source,
+            epoch_length,
+            epoch: StreamEpoch::default(),
+        }
+    }
+
+    /// The epoch of this dataset, which can be shared with the data loader that reads it.
+    pub fn epoch(&self) -> &StreamEpoch {
+        &self.epoch
+    }
+}
+
+impl<T> Dataset<T> for StreamingDataset<T> {
+    fn get(&self, index: usize) -> Option<T> {
+        if index >= self.epoch_length {
+            return None;
+        }
+        let epoch = self.epoch.get();
+        Some(match &self.source {
+            Source::Immut(gen) => gen.gen(),
+            Source::Mut(gen) => gen
+                .lock()
+                .expect("Streaming generator should not have panicked")
+                .gen(),
+            Source::Indexed(gen, seed) => gen.gen_at(epoch * self.epoch_length + index, *seed),
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.epoch_length
+    }
+}
+
+/// Moves a [`StreamEpoch`] to the next epoch every time the data loader starts one, starting at
+/// epoch 0.
+pub(crate) struct EpochDataLoader<O> {
+    loader: Arc<dyn DataLoader<O>>,
+    epoch: StreamEpoch,
+    started: AtomicUsize,
+}
+
+impl<O> EpochDataLoader<O> {
+    pub(crate) fn new(loader: Arc<dyn DataLoader<O>>, epoch: StreamEpoch) -> Self {
+        Self {
+            loader,
+            epoch,
+            started: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<O> DataLoader<O> for EpochDataLoader<O> {
+    fn iter<'a>(&'a self) -> Box<dyn DataLoaderIterator<O> + 'a> {
+        self.epoch.set(self.started.fetch_add(1, Ordering::Relaxed));
+        self.loader.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::data::{
+        dataloader::{batcher::Batcher, DataLoader, DataLoaderBuilder},
+        dataset::Dataset,
+    };
+
+    use super::{EpochDataLoader, StreamingDataset};
+    use crate::data::{IndexedDataGen, MutDataGen};
+
+    struct CountGen(u32);
+
+    impl MutDataGen for CountGen {
+        type Output = u32;
+
+        fn gen(&mut self) -> Self::Output {
+            self.0 += 1;
+            self.0
+        }
+
+        fn skip(&mut self, num: usize) {
+            self.0 += num as u32;
+        }
+    }
+
+    struct IndexGen;
+
+    impl IndexedDataGen for IndexGen {
+        type Output = usize;
+
+        fn gen_at(&self, index: usize, seed: u64) -> Self::Output {
+            index + seed as usize
+        }
+    }
+
+    #[test]
+    fn streaming_01() {
+        let counted = StreamingDataset::from_mut(CountGen(0), 4);
+        assert_eq!(counted.len(), 4);
+        assert_eq!(counted.get(0), Some(1));
+        assert_eq!(counted.get(0), Some(2));
+        assert_eq!(counted.get(4), None);
+
+        let indexed = StreamingDataset::indexed(IndexGen, 100, 4);
+        let epoch = |dataset: &StreamingDataset<usize>| {
+            (0..4).map(|i| dataset.get(i).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(epoch(&indexed), [100, 101, 102, 103]);
+        // Reading items again does not move to the next epoch
+        assert_eq!(epoch(&indexed), [100, 101, 102, 103]);
+        indexed.epoch().set(1);
+        assert_eq!(epoch(&indexed), [104, 105, 106, 107]);
+    }
+
+    struct VecBatcher;
+
+    impl Batcher<usize, Vec<usize>> for VecBatcher {
+        fn batch(&self, items: Vec<usize>) -> Vec<usize> {
+            items
+        }
+    }
+
+    #[test]
+    fn streaming_epochs_01() {
+        let dataset = StreamingDataset::indexed(IndexGen, 0, 3);
+        let epoch = dataset.epoch().clone();
+        let loader = DataLoaderBuilder::new(VecBatcher)
+            .batch_size(3)
+            .num_workers(2)
+            .build(dataset);
+        let loader = EpochDataLoader::new(loader, epoch);
+        let mut epochs = vec![];
+        for _ in 0..2 {
+            let mut items: Vec<_> = loader.iter().flatten().collect();
+            items.sort_unstable();
+            epochs.push(items);
+        }
+        assert_eq!(epochs, [[0, 1, 2], [3, 4, 5]]);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    compression::read_block,
+    format::{read_config, AcademyDatasetConfig, CONFIG_FILE},
+    BlockWriter, DatasetError, DatasetItem,
+};
+
+/// Opens the dataset at `data_path` for reading every block of it in order.
+pub(super) fn open_blocks<T: DatasetItem>(
+    data_path: &Path,
+) -> Result<AcademyDatasetConfig, DatasetError> {
+    let stored = read_config(data_path)?;
+    stored.check::<T>()?;
+    stored.config.compression.check_supported()?;
+    Ok(stored.config)
+}
+
+pub(super) fn read_nth_block<T: DeserializeOwned>(
+    data_path: &Path,
+    config: &AcademyDatasetConfig,
+    block: usize,
+) -> Result<Box<[T]>, DatasetError> {
+    let slice_path = data_path.join(format!("{block}.slice"));
+    Ok(read_block(&slice_path, config.compression)?.0)
+}
+
+/// Runs `write` on the directory the rewritten dataset should go in.
+///
+/// If `output_path` is the same directory as `data_path`, even through a different path to it, the
+/// dataset is written into a temporary directory next to it, which only replaces `data_path` once
+/// `write` succeeds. Files other than the dataset itself, such as saved splits, are carried over
+/// if `keep_other_files` is set.
+pub(super) fn rewrite(
+    data_path: &Path,
+    output_path: &Path,
+    keep_other_files: bool,
+    write: impl FnOnce(&Path) -> Result<(), DatasetError>,
+) -> Result<(), DatasetError> {
+    let is_dataset_file = |name: &str| name == CONFIG_FILE || name.ends_with(".slice");
+    let copy_other_files = |to: &Path| -> Result<(), DatasetError> {
+        for entry in std::fs::read_dir(data_path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if entry.file_type()?.is_file() && !is_dataset_file(&name.to_string_lossy()) {
+                std::fs::copy(entry.path(), to.join(name))?;
+            }
+        }
+        Ok(())
+    };
+
+    let data_path = &std::fs::canonicalize(data_path)?;
+    let in_place = output_path.exists() && std::fs::canonicalize(output_path)? == *data_path;
+    if !in_place {
+        std::fs::create_dir_all(output_path)?;
+        write(output_path)?;
+        if keep_other_files {
+            copy_other_files(output_path)?;
+        }
+        return Ok(());
+    }
+
+    let parent = data_path.parent().unwrap_or(Path::new("/"));
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".rewrite")
+        .tempdir_in(parent)?;
+    write(temp_dir.path())?;
+    if keep_other_files {
+        copy_other_files(temp_dir.path())?;
+    }
+
+    // Kept on disk until the new dataset is in place, so that a failed rewrite never loses the
+    // original
+    let old_dir = tempfile::Builder::new()
+        .prefix(".old")
+        .tempdir_in(parent)?
+        .into_path();
+    let old_path = old_dir.join("dataset");
+    if let Err(e) = std::fs::rename(data_path, &old_path) {
+        std::fs::remove_dir(&old_dir)?;
+        return Err(e.into());
+    }
+    if let Err(e) = std::fs::rename(temp_dir.path(), data_path) {
+        if let Err(restore_error) = std::fs::rename(&old_path, data_path) {
+            log::error!(
+                "{restore_error}, so the original dataset was left at {}",
+                old_path.display()
+            );
+        } else {
+            std::fs::remove_dir(&old_dir)?;
+        }
+        return Err(e.into());
+    }
+    std::fs::remove_dir_all(&old_dir)?;
+    Ok(())
+}
+
+/// Rewrites the dataset at `data_path` into `output_path` with its items in a uniformly random
+/// order, which may be `data_path` itself.
+///
+/// The items are first scattered into temporary bucket files next to `output_path` so that each
+/// bucket fits in `memory_budget` bytes, then each bucket is shuffled in memory and written out.
+/// One file is kept open per bucket. Files such as saved splits are not carried over, as the
+/// indices in them no longer refer to the same items.
+pub fn reshuffle_dataset<T>(
+    data_path: &Path,
+    output_path: &Path,
+    memory_budget: usize,
+    seed: u64,
+) -> Result<(), DatasetError>
+where
+    T: Serialize + DeserializeOwned + DatasetItem,
+{
+    let config = open_blocks::<T>(data_path)?;
+    let mut total_bytes = 0;
+    for (i, block) in config.blocks.iter().enumerate() {
+        total_bytes += match block.bytes {
+            // Datasets written before version 4 do not record their sizes
+            0 => std::fs::metadata(data_path.join(format!("{i}.slice")))?.len(),
+            bytes => bytes,
+        } + (block.items * std::mem::size_of::<T>()) as u64;
+    }
+    let bucket_count = total_bytes.div_ceil(memory_budget.max(1) as u64).max(1) as usize;
+
+    std::fs::create_dir_all(output_path)?;
+    let bucket_dir = tempfile::Builder::new()
+        .prefix(".buckets")
+        .tempdir_in(output_path.parent().unwrap_or(output_path))?;
+    let bucket_path = |bucket: usize| bucket_dir.path().join(format!("{bucket}.bucket"));
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut bucket_lengths = vec![0usize; bucket_count];
+    {
+        let mut buckets = (0..bucket_count)
+            .map(|bucket| Ok(BufWriter::new(File::create(bucket_path(bucket))?)))
+            .collect::<Result<Vec<_>, DatasetError>>()?;
+        for block in 0..config.blocks.len() {
+            for item in read_nth_block::<T>(data_path, &config, block)?.iter() {
+                let bucket = rng.gen_range(0..bucket_count);
+                bincode::serialize_into(&mut buckets[bucket], item)?;
+                bucket_lengths[bucket] += 1;
+            }
+        }
+        for mut bucket in buckets {
+            bucket.flush()?;
+        }
+    }
+
+    rewrite(data_path, output_path, false, |output_path| {
+        let mut writer =
+            BlockWriter::create(output_path, config.block_memory_size, config.compression)?
+                .with_fingerprint(config.fingerprint);
+        for (bucket, length) in bucket_lengths.into_iter().enumerate() {
+            let mut reader = BufReader::new(File::open(bucket_path(bucket))?);
+            let mut items = (0..length)
+                .map(|_| bincode::deserialize_from(&mut reader))
+                .collect::<Result<Vec<T>, _>>()?;
+            items.shuffle(&mut rng);
+            for item in items {
+                writer.push(item)?;
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    })
+}
+
+/// Rewrites the dataset at `data_path` into `output_path` with blocks of `block_memory_size`
+/// bytes, merging or splitting the existing blocks. `output_path` may be `data_path` itself.
+///
+/// The items keep their order, so files such as saved splits are carried over.
+pub fn reblock_dataset<T>(
+    data_path: &Path,
+    output_path: &Path,
+    block_memory_size: usize,
+) -> Result<(), DatasetError>
+where
+    T: Serialize + DeserializeOwned + DatasetItem,
+{
+    let config = open_blocks::<T>(data_path)?;
+    rewrite(data_path, output_path, true, |output_path| {
+        let mut writer = BlockWriter::create(output_path, block_memory_size, config.compression)?
+            .with_fingerprint(config.fingerprint);
+        for block in 0..config.blocks.len() {
+            for item in read_nth_block::<T>(data_path, &config, block)?.into_vec() {
+                writer.push(item)?;
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::data::dataset::Dataset;
+    use tempfile::tempdir;
+
+    use super::{reblock_dataset, reshuffle_dataset};
+    use crate::data::{create_dataset, AcademyDataset, DataGenerator, IndexedDataGen};
+
+    struct IndexGen;
+
+    impl IndexedDataGen for IndexGen {
+        type Output = u32;
+
+        fn gen_at(&self, index: usize, _seed: u64) -> Self::Output {
+            index as u32
+        }
+    }
+
+    fn items(data_path: &std::path::Path) -> Vec<u32> {
+        let db = AcademyDataset::<u32>::new(data_path.into(), 1000);
+        (0..db.len()).map(|i| db.get(i).unwrap()).collect()
+    }
+
+    #[test]
+    fn reshuffle_01() {
+        let dir = tempdir().unwrap();
+        let data_path = dir.path().join("data");
+        let shuffled_path = dir.path().join("shuffled");
+        create_dataset(
+            500,
+            data_path.clone(),
+            100,
+            DataGenerator::Indexed(&IndexGen, 0),
+        );
+
+        // A small budget forces several buckets
+        reshuffle_dataset::<u32>(&data_path, &shuffled_path, 500, 8000).unwrap();
+        let shuffled = items(&shuffled_path);
+        assert_ne!(shuffled, items(&data_path));
+        let mut sorted = shuffled.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, items(&data_path));
+
+        reshuffle_dataset::<u32>(&data_path, &data_path, 500, 8000).unwrap();
+        assert_eq!(items(&data_path), shuffled);
+    }
+
+    #[test]
+    fn reblock_01() {
+        let dir = tempdir().unwrap();
+        let data_path = dir.path().join("data");
+        create_dataset(
+            500,
+            data_path.clone(),
+            100,
+            DataGenerator::Indexed(&IndexGen, 0),
+        );
+        std::fs::write(data_path.join("split.dat"), b"split").unwrap();
+        let before = items(&data_path);
+        let block_count = AcademyDataset::<u32>::new(data_path.clone(), 1000).block_count();
+
+        reblock_dataset::<u32>(&data_path, &data_path, 400).unwrap();
+        let db = AcademyDataset::<u32>::new(data_path.clone(), 1000);
+        assert!(db.block_count() < block_count);
+        assert_eq!(items(&data_path), before);
+        assert!(data_path.join("split.dat").exists());
+        assert!(!data_path
+            .join(format!("{}.slice", db.block_count()))
+            .exists());
+
+        // Another path to the same directory is still rewritten in place
+        reblock_dataset::<u32>(&data_path, &data_path.join("."), 200).unwrap();
+        assert!(
+            AcademyDataset::<u32>::new(data_path.clone(), 1000).block_count() > db.block_count()
+        );
+        assert_eq!(items(&data_path), before);
+        assert!(data_path.join("split.dat").exists());
+
+        #[cfg(unix)]
+        {
+            let link_path = dir.path().join("link");
+            std::os::unix::fs::symlink(&data_path, &link_path).unwrap();
+            reblock_dataset::<u32>(&data_path, &link_path, 100).unwrap();
+            assert_eq!(items(&data_path), before);
+        }
+
+        // The original is only kept until the rewritten dataset is in place
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.iter().all(|name| !name.starts_with('.')));
+
+        // Writing over another dataset replaces it
+        let other_path = dir.path().join("other");
+        create_dataset(
+            50,
+            other_path.clone(),
+            100,
+            DataGenerator::Indexed(&IndexGen, 0),
+        );
+        reblock_dataset::<u32>(&data_path, &other_path, 400).unwrap();
+        assert_eq!(items(&other_path), before);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{marker::PhantomData, ops::Range, path::Path, sync::Arc};
+
+use burn::data::dataset::Dataset;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use super::DatasetError;
+
+/// The items of `dataset` at `indices`, in that order.
+#[derive(Clone)]
+pub struct SubsetDataset<D> {
+    dataset: D,
+    indices: Arc<[usize]>,
+}
+
+/// The indices kept by a filter, as saved by [`SubsetDataset::filter`].
+#[derive(Serialize, Deserialize)]
+struct FilterIndex {
+    /// The id of the predicate the indices were computed with
+    predicate_id: String,
+    dataset_length: usize,
+    indices: Vec<usize>,
+}
+
+impl<D> SubsetDataset<D> {
+    pub fn new(dataset: D, indices: impl Into<Arc<[usize]>>) -> Self {
+        Self {
+            dataset,
+            indices: indices.into(),
+        }
+    }
+
+    /// The items of `dataset` in `range`.
+    pub fn range(dataset: D, range: Range<usize>) -> Self {
+        Self::new(dataset, range.collect::<Vec<_>>())
+    }
+
+    /// The items of `dataset` that satisfy `predicate`.
+    ///
+    /// The indices of the kept items are saved at `index_path` and reused as long as the length of
+    /// `dataset` and `predicate_id` do not change. `predicate_id` names the predicate and should be
+    /// changed, such as by bumping a version in it, whenever `predicate` changes.
+    pub fn filter<I>(
+        dataset: D,
+        index_path: &Path,
+        predicate_id: &str,
+        predicate: impl Fn(&I) -> bool + Sync,
+    ) -> Result<Self, DatasetError>
+    where
+        D: Dataset<I>,
+    {
+        if index_path.exists() {
+            match bincode::deserialize::<FilterIndex>(&std::fs::read(index_path)?) {
+                Ok(index)
+                    if index.predicate_id == predicate_id
+                        && index.dataset_length == dataset.len() =>
+                {
+                    return Ok(Self::new(dataset, index.indices))
+                }
+                Ok(_) => log::warn!(
+                    "Filter index at {} was made with a different predicate or for a dataset of a different length and will be recreated",
+                    index_path.display()
+                ),
+                Err(e) => log::warn!(
+                    "Filter index at {} could not be read and will be recreated: {e}",
+                    index_path.display()
+                ),
+            }
+        }
+
+        let indices: Vec<_> = (0..dataset.len())
+            .into_par_iter()
+            .filter(|i| {
+                dataset
+                    .get(*i)
+                    .map(|item| predicate(&item))
+                    .unwrap_or_default()
+            })
+            .collect();
+        let index = FilterIndex {
+            predicate_id: predicate_id.to_string(),
+            dataset_length: dataset.len(),
+            indices,
+        };
+        std::fs::write(index_path, bincode::serialize(&index)?)?;
+        Ok(Self::new(dataset, index.indices))
+    }
+
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.dataset
+    }
+}
+
+impl<D: Dataset<I>, I> Dataset<I> for SubsetDataset<D> {
+    fn get(&self, index: usize) -> Option<I> {
+        self.dataset.get(*self.indices.get(index)?)
+    }
+
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+}
+
+/// The items of several datasets, one after the other.
+pub struct ConcatDataset<D> {
+    datasets: Vec<D>,
+    /// The index one past the last item of each dataset
+    ends: Vec<usize>,
+}
+
+impl<D> ConcatDataset<D> {
+    pub fn new<I>(datasets: Vec<D>) -> Self
+    where
+        D: Dataset<I>,
+    {
+        let ends = datasets
+            .iter()
+            .scan(0, |end, dataset| {
+                *end += dataset.len();
+                Some(*end)
+            })
+            .collect();
+        Self { datasets, ends }
+    }
+
+    pub fn datasets(&self) -> &[D] {
+        &self.datasets
+    }
+}
+
+impl<D: Dataset<I>, I> Dataset<I> for ConcatDataset<D> {
+    fn get(&self, index: usize) -> Option<I> {
+        let dataset = self.ends.partition_point(|end| *end <= index);
+        let start = dataset
+            .checked_sub(1)
+            .map(|previous| self.ends[previous])
+            .unwrap_or_default();
+        self.datasets.get(dataset)?.get(index - start)
+    }
+
+    fn len(&self) -> usize {
+        self.ends.last().copied().unwrap_or_default()
+    }
+}
+
+/// The items of `dataset` transformed by `map` as they are read.
+pub struct MapDataset<D, F, I> {
+    dataset: D,
+    map: F,
+    _phantom: PhantomData<fn(I)>,
+}
+
+impl<D, F, I> MapDataset<D, F, I> {
+    pub fn new(dataset: D, map: F) -> Self {
+        Self {
+            dataset,
+            map,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<D, F, I, O> Dataset<O> for MapDataset<D, F, I>
+where
+    D: Dataset<I>,
+    F: Fn(I) -> O + Send + Sync,
+{
+    fn get(&self, index: usize) -> Option<O> {
+        self.dataset.get(index).map(&self.map)
+    }
+
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use burn::data::dataset::{Dataset, InMemDataset};
+    use tempfile::tempdir;
+
+    use super::{ConcatDataset, MapDataset, SubsetDataset};
+
+    #[test]
+    fn views_01() {
+        let concat = ConcatDataset::new(vec![
+            InMemDataset::new(vec![0, 1, 2]),
+            InMemDataset::new(vec![]),
+            InMemDataset::new(vec![3, 4]),
+        ]);
+        assert_eq!(concat.len(), 5);
+        assert_eq!(
+            (0..6).map(|i| concat.get(i)).collect::<Vec<_>>(),
+            [Some(0), Some(1), Some(2), Some(3), Some(4), None]
+        );
+
+        let mapped = MapDataset::new(SubsetDataset::range(concat, 1..4), |x: i32| x * 10);
+        assert_eq!(mapped.len(), 3);
+        assert_eq!(mapped.get(0), Some(10));
+        assert_eq!(mapped.get(2), Some(30));
+        assert_eq!(mapped.get(3), None);
+    }
+
+    #[test]
+    fn filter_01() {
+        let dir = tempdir().unwrap();
+        let index_path = dir.path().join("even.idx");
+        let dataset = Arc::new(InMemDataset::new((0..100).collect::<Vec<i32>>()));
+        let even =
+            SubsetDataset::filter(dataset.clone(), &index_path, "even", |x: &i32| x % 2 == 0)
+                .unwrap();
+        assert_eq!(even.len(), 50);
+        assert_eq!(even.get(10), Some(20));
+
+        // The saved index is reused while the predicate id stays the same
+        let reused =
+            SubsetDataset::filter(dataset.clone(), &index_path, "even", |_: &i32| false).unwrap();
+        assert_eq!(reused.indices(), even.indices());
+
+        // A different predicate id recomputes it
+        let small =
+            SubsetDataset::filter(dataset, &index_path, "small", |x: &i32| *x < 10).unwrap();
+        assert_eq!(small.indices(), (0..10).collect::<Vec<_>>());
+    }
+}
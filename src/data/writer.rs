@@ -0,0 +1,127 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use super::{
+    compression::write_block,
+    format::{write_config, AcademyDatasetConfig, BlockInfo, CONFIG_FILE},
+    Compression, DatasetError, DatasetItem,
+};
+
+/// Appends items to a dataset directory, starting a new block whenever the current one reaches
+/// `block_memory_size` serialized bytes.
+pub(crate) struct BlockWriter<T> {
+    data_path: PathBuf,
+    config: AcademyDatasetConfig,
+    block: Vec<T>,
+    /// The serialized size of `block`
+    bytes: u64,
+}
+
+/// The length prefix of a serialized block
+const BLOCK_PREFIX_BYTES: u64 = 8;
+
+impl<T: Serialize + DatasetItem> BlockWriter<T> {
+    /// Continues writing after the blocks already in `config`.
+    pub(crate) fn new(data_path: PathBuf, config: AcademyDatasetConfig) -> Self {
+        Self {
+            data_path,
+            config,
+            block: vec![],
+            bytes: BLOCK_PREFIX_BYTES,
+        }
+    }
+
+    /// Starts a new dataset at `data_path`, replacing any dataset already there.
+    pub(crate) fn create(
+        data_path: &Path,
+        block_memory_size: usize,
+        compression: Compression,
+    ) -> Result<Self, DatasetError> {
+        compression.check_supported()?;
+        std::fs::create_dir_all(data_path)?;
+        let config_path = data_path.join(CONFIG_FILE);
+        if config_path.exists() {
+            std::fs::remove_file(config_path)?;
+        }
+        Ok(Self::new(
+            data_path.into(),
+            AcademyDatasetConfig {
+                block_memory_size,
+                length: 0,
+                compression,
+                fingerprint: None,
+                blocks: vec![],
+            },
+        ))
+    }
+
+    /// Records the fingerprint of the generator the items came from.
+    pub(crate) fn with_fingerprint(mut self, fingerprint: Option<u64>) -> Self {
+        self.config.fingerprint = fingerprint;
+        self
+    }
+
+    pub(crate) fn config(&self) -> &AcademyDatasetConfig {
+        &self.config
+    }
+
+    /// Adds `item`, returning the number of items and the path of the block it completed, if any.
+    pub(crate) fn push(&mut self, item: T) -> Result<Option<(usize, PathBuf)>, DatasetError> {
+        self.bytes += bincode::serialized_size(&item)?;
+        self.block.push(item);
+        if self.bytes >= self.config.block_memory_size as u64 {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Writes the current block even if it is not full.
+    pub(crate) fn flush(&mut self) -> Result<Option<(usize, PathBuf)>, DatasetError> {
+        if self.block.is_empty() {
+            return Ok(None);
+        }
+        let file_path = self
+            .data_path
+            .join(format!("{}.slice", self.config.blocks.len()));
+        write_block(&file_path, &self.block, self.config.compression)?;
+
+        let items = self.block.len();
+        self.config.blocks.push(BlockInfo {
+            first_item: 0,
+            items,
+            byte_offset: 0,
+            bytes: self.bytes,
+        });
+        self.config.fix_offsets();
+        self.block.clear();
+        self.bytes = BLOCK_PREFIX_BYTES;
+        Ok(Some((items, file_path)))
+    }
+
+    /// Saves the blocks written so far, so that an interrupted write can be resumed.
+    pub(crate) fn write_config(&self) -> Result<(), DatasetError> {
+        write_config::<T>(&self.data_path, &self.config)
+    }
+
+    /// Writes the last block and the config, and removes any slices left over from an earlier
+    /// dataset in the same directory. The length of the dataset is set to the items written.
+    pub(crate) fn finish(mut self) -> Result<AcademyDatasetConfig, DatasetError> {
+        self.flush()?;
+        self.config.length = self.config.blocks.iter().map(|block| block.items).sum();
+        self.write_config()?;
+
+        let mut i = self.config.blocks.len();
+        loop {
+            let path = self.data_path.join(format!("{i}.slice"));
+            if path.try_exists()? {
+                std::fs::remove_file(path)?;
+            } else {
+                break;
+            }
+            i += 1;
+        }
+        Ok(self.config)
+    }
+}
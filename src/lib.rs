@@ -5,8 +5,9 @@ use std::{
     fs::File,
     io::Write,
     marker::PhantomData,
+    ops::Range,
     path::{Path, PathBuf},
-    sync::{Mutex, Once},
+    sync::{Arc, Mutex, Once},
     time::Instant,
 };
 
@@ -15,10 +16,11 @@ use burn::{
     data::{dataloader::{batcher::Batcher, DataLoaderBuilder}, dataset::Dataset},
     lr_scheduler::noam::NoamLrSchedulerConfig,
     module::{AutodiffModule, Module},
+    nn::loss::{MSELoss, Reduction},
     optim::AdamConfig,
     record::CompactRecorder,
     tensor::{
-        backend::{AutodiffBackend, Backend}, Tensor,
+        backend::{AutodiffBackend, Backend}, Data, Shape, Tensor,
     },
     train::{
         metric::{
@@ -30,7 +32,13 @@ use burn::{
     },
 };
 use chrono::{Datelike, Timelike};
-use data::AcademyDataset;
+use data::{
+    AcademyDataset, Augmentations, Augmenter, BlockShuffledDataset, DatasetError, DatasetItem,
+    EpochDataLoader, Normalization, NpyWriter, SampleWeight, Scaler, StreamEpoch,
+    StreamingDataset,
+};
+#[cfg(target_endian = "little")]
+use data::MappedDataset;
 pub use rand;
 use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -145,40 +153,241 @@ impl<M, B> TrainingModel<M, B> {
 pub struct RegressionBatch<B: Backend, const I: usize, const T: usize> {
     pub inputs: Tensor<B, I>,
     pub targets: Tensor<B, T>,
+    /// The sample weight of each item, or `None` if no item of the batch has one
+    pub weights: Option<Tensor<B, 1>>,
+}
+
+impl<B: Backend, const I: usize> RegressionBatch<B, I, 2> {
+    /// The mean squared error of `output` against the targets, with each item counting as much
+    /// as its sample weight.
+    pub fn mse_loss(&self, output: Tensor<B, 2>) -> Tensor<B, 1> {
+        match &self.weights {
+            Some(weights) => {
+                let errors = item_squared_errors(output, self.targets.clone());
+                errors.mul(weights.clone()).sum().div(weights.clone().sum())
+            }
+            None => MSELoss::new().forward(output, self.targets.clone(), Reduction::Auto),
+        }
+    }
+}
+
+/// The mean squared error of each item of `output` of shape `[batch, targets]`.
+pub fn item_squared_errors<B: Backend>(
+    output: Tensor<B, 2>,
+    targets: Tensor<B, 2>,
+) -> Tensor<B, 1> {
+    let [batch_size, _] = output.dims();
+    output.sub(targets).powf(2.0).mean_dim(1).reshape([batch_size])
 }
 
 pub struct RegressionBatcher<B: Backend> {
     device: B::Device,
+    scaler: Option<Arc<Scaler>>,
+    augmenter: Option<Augmenter>,
 }
 
 impl<B: Backend> RegressionBatcher<B> {
     pub fn new(device: B::Device) -> Self {
-        Self { device }
+        Self {
+            device,
+            scaler: None,
+            augmenter: None,
+        }
+    }
+
+    /// Normalizes the inputs and targets of every batch with `scaler`.
+    pub fn with_scaler(mut self, scaler: Arc<Scaler>) -> Self {
+        self.scaler = Some(scaler);
+        self
+    }
+
+    pub fn scaler(&self) -> Option<&Scaler> {
+        self.scaler.as_deref()
+    }
+
+    /// Randomly augments the inputs of every batch, after they are normalized. Only meant for
+    /// training batches.
+    pub fn with_augmentations(mut self, augmentations: Augmentations, seed: u64) -> Self {
+        self.augmenter = augmentations
+            .is_enabled()
+            .then(|| Augmenter::new(augmentations, seed));
+        self
+    }
+
+    /// Normalizes and augments inputs of shape `[batch, rows, features]`, moving them to the
+    /// device. Inputs that are augmented are only read back from where they were built, before
+    /// they are moved.
+    fn prepare_inputs(&self, inputs: Tensor<B, 3>) -> Tensor<B, 3> {
+        if self.augmenter.is_some() {
+            let dims = inputs.dims();
+            return self.prepare_input_values(inputs.into_data().convert().value, dims);
+        }
+        let inputs = inputs.to_device(&self.device);
+        match &self.scaler {
+            Some(scaler) => scaler.normalize_inputs(inputs),
+            None => inputs,
+        }
+    }
+
+    /// Like [`RegressionBatcher::prepare_inputs`] for raw values of shape `dims`, which are
+    /// normalized and augmented on the host if there are augmentations.
+    fn prepare_input_values(&self, mut values: Vec<f32>, dims: [usize; 3]) -> Tensor<B, 3> {
+        let Some(augmenter) = &self.augmenter else {
+            let inputs = Tensor::from_data_device(
+                Data::new(values, Shape::new(dims)).convert(),
+                &self.device,
+            );
+            return match &self.scaler {
+                Some(scaler) => scaler.normalize_inputs(inputs),
+                None => inputs,
+            };
+        };
+        if let Some(scaler) = &self.scaler {
+            scaler.normalize_input_values(&mut values);
+        }
+        let [_, steps, features] = dims;
+        augmenter.augment_values(&mut values, [steps, features]);
+        Tensor::from_data_device(Data::new(values, Shape::new(dims)).convert(), &self.device)
+    }
+
+    /// Normalizes targets of shape `[batch, targets]` and lays the prepared inputs out the way
+    /// the models expect them.
+    fn finish_batch(
+        &self,
+        inputs: Tensor<B, 3>,
+        targets: Tensor<B, 2>,
+        weights: Option<Tensor<B, 1>>,
+    ) -> RegressionBatch<B, 3, 2> {
+        let targets = match &self.scaler {
+            Some(scaler) => scaler.normalize_targets(targets),
+            None => targets,
+        };
+        let [batch_size, a, b] = inputs.dims();
+        let inputs = inputs.reshape([batch_size, a * b / 2, 2]);
+        RegressionBatch { inputs, targets, weights }
+    }
+
+    /// Batches a contiguous range of records, building the tensors directly from the mapped
+    /// memory instead of from individual items.
+    #[cfg(target_endian = "little")]
+    pub fn batch_mapped(
+        &self,
+        dataset: &MappedDataset,
+        range: Range<usize>,
+    ) -> Option<RegressionBatch<B, 3, 2>> {
+        let inputs = dataset.inputs(range.clone())?;
+        let targets = dataset.targets(range.clone())?;
+        let [a, b] = dataset.input_shape();
+
+        let inputs = self.prepare_input_values(inputs.to_vec(), [range.len(), a, b]);
+        let targets = Tensor::from_data_device(
+            Data::new(targets.to_vec(), Shape::new([range.len(), dataset.target_len()])).convert(),
+            &self.device,
+        );
+
+        Some(self.finish_batch(inputs, targets, None))
     }
 }
 
-impl<B: Backend, Item: Into<(Tensor<B, 2>, Tensor<B, 1>)>> Batcher<Item, RegressionBatch<B, 3, 2>>
-    for RegressionBatcher<B>
-{
-    fn batch(&self, items: Vec<Item>) -> RegressionBatch<B, 3, 2> {
+impl<B: Backend> RegressionBatcher<B> {
+    /// Stacks the inputs and targets of `items` into a batch with the given sample weights.
+    fn batch_items<Item>(
+        &self,
+        items: Vec<Item>,
+        weights: Option<Tensor<B, 1>>,
+    ) -> RegressionBatch<B, 3, 2>
+    where
+        Item: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    {
         let (inputs, targets) = items
             .into_iter()
             .map(|item| Into::<(Tensor<B, 2>, Tensor<B, 1>)>::into(item))
             .map(|(input, target)| {
                 let [a, b] = input.dims();
-                let len = a * b / 2;
                 let target_len = target.dims()[0];
-                (input.reshape([1, len, 2]), target.reshape([1, target_len]))
+                (input.reshape([1, a, b]), target.reshape([1, target_len]))
             })
             .unzip();
 
-        let inputs = Tensor::cat(inputs, 0).to_device(&self.device);
+        let inputs = self.prepare_inputs(Tensor::cat(inputs, 0));
         let targets = Tensor::cat(targets, 0).to_device(&self.device);
 
-        RegressionBatch { inputs, targets }
+        self.finish_batch(inputs, targets, weights)
     }
 }
 
+impl<B, Item> Batcher<Item, RegressionBatch<B, 3, 2>> for RegressionBatcher<B>
+where
+    B: Backend,
+    Item: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+{
+    fn batch(&self, items: Vec<Item>) -> RegressionBatch<B, 3, 2> {
+        self.batch_items(items, None)
+    }
+}
+
+/// A [`RegressionBatcher`] that also reads the [`SampleWeight`] of every item, so that each item
+/// counts as much as its weight in the loss.
+pub struct WeightedRegressionBatcher<B: Backend>(RegressionBatcher<B>);
+
+impl<B: Backend> WeightedRegressionBatcher<B> {
+    pub fn new(batcher: RegressionBatcher<B>) -> Self {
+        Self(batcher)
+    }
+
+    pub fn batcher(&self) -> &RegressionBatcher<B> {
+        &self.0
+    }
+}
+
+impl<B: Backend> From<RegressionBatcher<B>> for WeightedRegressionBatcher<B> {
+    fn from(batcher: RegressionBatcher<B>) -> Self {
+        Self::new(batcher)
+    }
+}
+
+impl<B, Item> Batcher<Item, RegressionBatch<B, 3, 2>> for WeightedRegressionBatcher<B>
+where
+    B: Backend,
+    Item: Into<(Tensor<B, 2>, Tensor<B, 1>)> + SampleWeight,
+{
+    fn batch(&self, items: Vec<Item>) -> RegressionBatch<B, 3, 2> {
+        let weights: Vec<_> = items.iter().map(SampleWeight::sample_weight).collect();
+        // Unweighted items count as much as an item of weight 1
+        let weights = weights.iter().any(Option::is_some).then(|| {
+            Tensor::from_data_device(
+                Data::new(
+                    weights.iter().map(|weight| weight.unwrap_or(1.0)).collect(),
+                    Shape::new([weights.len()]),
+                )
+                .convert(),
+                &self.0.device,
+            )
+        });
+        self.0.batch_items(items, weights)
+    }
+}
+
+/// Whether training reads the [`SampleWeight`] of each item, either [`Unweighted`] or
+/// [`Weighted`].
+pub trait Weighting: 'static {
+    type Batcher<B: Backend>: From<RegressionBatcher<B>> + Send + Sync + 'static;
+}
+
+/// Every item counts the same, batched by a [`RegressionBatcher`].
+pub struct Unweighted;
+
+/// Each item counts as much as its [`SampleWeight`], batched by a [`WeightedRegressionBatcher`].
+pub struct Weighted;
+
+impl Weighting for Unweighted {
+    type Batcher<B: Backend> = RegressionBatcher<B>;
+}
+
+impl Weighting for Weighted {
+    type Batcher<B: Backend> = WeightedRegressionBatcher<B>;
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TrainingConfig<T> {
     pub model_config: T,
@@ -197,6 +406,21 @@ pub struct TrainingConfig<T> {
     pub stop_condition_epochs: usize,
     #[serde(default = "default_learning_rate_warmup_steps")]
     pub learning_rate_warmup_steps: usize,
+    #[serde(default = "default_shuffle_window")]
+    pub shuffle_window: usize,
+    /// How many blocks past the current shuffle window to load in the background. 0 disables
+    /// prefetching.
+    #[serde(default)]
+    pub prefetch_blocks: usize,
+    /// How inputs and targets are scaled before reaching the model, fitted on the training
+    /// dataset. The fitted scaler is saved as `scaler.json` in the artifact dir, and the losses
+    /// are in normalized units.
+    #[serde(default)]
+    pub normalization: Normalization,
+    /// Augmentations applied to training batches, seeded from `seed`. Validation batches are
+    /// never augmented.
+    #[serde(default)]
+    pub augmentations: Augmentations,
 }
 
 fn default_num_epochs() -> usize {
@@ -227,6 +451,10 @@ fn default_learning_rate_warmup_steps() -> usize {
     1000
 }
 
+fn default_shuffle_window() -> usize {
+    4
+}
+
 impl<T: Serialize + DeserializeOwned> Config for TrainingConfig<T> {}
 
 impl<T> TrainingConfig<T> {
@@ -241,14 +469,54 @@ impl<T> TrainingConfig<T> {
             init_learning_rate: default_init_learning_rate(),
             stop_condition_epochs: default_stop_condition_epochs(),
             learning_rate_warmup_steps: default_learning_rate_warmup_steps(),
+            shuffle_window: default_shuffle_window(),
+            prefetch_blocks: 0,
+            normalization: Normalization::None,
+            augmentations: Augmentations::default(),
         }
     }
 }
 
 #[derive(Config)]
 pub struct Statistics {
+    /// The mean squared error of the validation items, each counting the same
     loss_mean: f32,
-    loss_std_dev: f32
+    loss_std_dev: f32,
+    /// The mean squared error of the validation items weighted by their sample weights, if any
+    /// item has one
+    weighted_loss_mean: Option<f32>,
+    weighted_loss_std_dev: Option<f32>,
+}
+
+impl Statistics {
+    /// The mean and sample standard deviation of the squared error of each item of `output`,
+    /// along with the weighted mean and standard deviation if there are `weights`.
+    fn from_outputs<B: Backend>(
+        output: Tensor<B, 2>,
+        targets: Tensor<B, 2>,
+        weights: Option<Tensor<B, 1>>,
+    ) -> Self {
+        let errors = item_squared_errors(output, targets);
+        let (var, mean) = errors.clone().var_mean(0);
+        let (weighted_loss_mean, weighted_loss_std_dev) = match weights {
+            Some(weights) => {
+                let total = weights.clone().sum();
+                let mean = errors.clone().mul(weights.clone()).sum().div(total.clone());
+                let var = errors.sub(mean.clone()).powf(2.0).mul(weights).sum().div(total);
+                (
+                    Some(mean.into_scalar().to_f32().unwrap()),
+                    Some(var.into_scalar().to_f32().unwrap().sqrt()),
+                )
+            }
+            None => (None, None),
+        };
+        Self {
+            loss_mean: mean.into_scalar().to_f32().unwrap(),
+            loss_std_dev: var.into_scalar().to_f32().unwrap().sqrt(),
+            weighted_loss_mean,
+            weighted_loss_std_dev,
+        }
+    }
 }
 
 static LOGGING: Once = Once::new();
@@ -303,7 +571,166 @@ where
         + Into<(Tensor<B, 2>, Tensor<B, 1>)>
         + Into<(Tensor<B::InnerBackend, 2>, Tensor<B::InnerBackend, 1>)>
         + DeserializeOwned
+        + DatasetItem
+        + 'static,
+{
+    let mut training_dataset = BlockShuffledDataset::new(
+        AcademyDataset::new(training_data_path, max_memory_usage),
+        config.shuffle_window,
+        config.seed,
+    );
+    if config.prefetch_blocks > 0 {
+        training_dataset = training_dataset.with_prefetch(config.prefetch_blocks);
+    }
+    let training_blocks = training_dataset.dataset().clone();
+    // Fitted on the whole dataset so that the statistics saved next to it can be reused
+    let scaler = (config.normalization != Normalization::None).then(|| {
+        Scaler::fit_dataset::<B::InnerBackend, I>(&training_blocks, config.normalization)
+            .expect("Training dataset statistics should be readable and writable")
+    });
+
+    let stats = train_regression_scaled::<B, T, I, _, _, Unweighted>(
+        artifact_dir,
+        training_dataset,
+        AcademyDataset::<I>::new(testing_data_path, max_memory_usage),
+        scaler,
+        TrainingEpochs::Unshuffled,
+        config,
+        device,
+    );
+    log::info!("Training data cache: {:?}", training_blocks.cache_stats());
+    stats
+}
+
+/// Trains on items generated on the fly by `training_dataset`, validating on the persisted
+/// dataset at `testing_data_path`.
+///
+/// The scaler is fitted on the validation dataset, as fitting it on `training_dataset` would
+/// generate items that training never sees.
+pub fn train_regression_streaming<B, T, I>(
+    artifact_dir: &str,
+    training_dataset: StreamingDataset<I>,
+    testing_data_path: PathBuf,
+    max_memory_usage: usize,
+    config: TrainingConfig<T::Config>,
+    device: B::Device,
+) -> Statistics
+where
+    B: AutodiffBackend,
+    T: Model<B> + AutodiffModule<B>,
+    TrainingModel<T, B>: TrainStep<RegressionBatch<B, 3, 2>, RegressionOutput<B>>,
+    <TrainingModel<T, B> as AutodiffModule<B>>::InnerModule:
+        ValidStep<RegressionBatch<B::InnerBackend, 3, 2>, RegressionOutput<B::InnerBackend>>,
+    I: Send
+        + Sync
+        + Clone
+        + Debug
+        + Into<(Tensor<B, 2>, Tensor<B, 1>)>
+        + Into<(Tensor<B::InnerBackend, 2>, Tensor<B::InnerBackend, 1>)>
+        + DeserializeOwned
+        + DatasetItem
         + 'static,
+{
+    let validation_dataset = AcademyDataset::<I>::new(testing_data_path, max_memory_usage);
+    let scaler = (config.normalization != Normalization::None).then(|| {
+        Scaler::fit_dataset::<B::InnerBackend, I>(&validation_dataset, config.normalization)
+            .expect("Validation dataset statistics should be readable and writable")
+    });
+    let epoch = training_dataset.epoch().clone();
+    train_regression_scaled::<B, T, I, _, _, Unweighted>(
+        artifact_dir,
+        training_dataset,
+        validation_dataset,
+        scaler,
+        TrainingEpochs::Streamed(epoch),
+        config,
+        device,
+    )
+}
+
+/// Trains on any datasets, such as views built from [`data::ConcatDataset`],
+/// [`data::SubsetDataset`] or [`data::MapDataset`]. With [`Weighted`] as `W`, each item counts
+/// as much as its [`SampleWeight`] in the loss, and with [`Unweighted`] every item counts the
+/// same.
+///
+/// The training dataset is reshuffled every epoch, seeded from `config.seed`.
+pub fn train_regression_on<B, T, I, DT, DV, W>(
+    artifact_dir: &str,
+    training_dataset: DT,
+    validation_dataset: DV,
+    config: TrainingConfig<T::Config>,
+    device: B::Device,
+) -> Statistics
+where
+    B: AutodiffBackend,
+    T: Model<B> + AutodiffModule<B>,
+    TrainingModel<T, B>: TrainStep<RegressionBatch<B, 3, 2>, RegressionOutput<B>>,
+    <TrainingModel<T, B> as AutodiffModule<B>>::InnerModule:
+        ValidStep<RegressionBatch<B::InnerBackend, 3, 2>, RegressionOutput<B::InnerBackend>>,
+    I: Send
+        + Sync
+        + Clone
+        + Debug
+        + Into<(Tensor<B, 2>, Tensor<B, 1>)>
+        + Into<(Tensor<B::InnerBackend, 2>, Tensor<B::InnerBackend, 1>)>
+        + 'static,
+    DT: Dataset<I> + 'static,
+    DV: Dataset<I> + 'static,
+    W: Weighting,
+    W::Batcher<B>: Batcher<I, RegressionBatch<B, 3, 2>>,
+    W::Batcher<B::InnerBackend>: Batcher<I, RegressionBatch<B::InnerBackend, 3, 2>>,
+{
+    let scaler = (config.normalization != Normalization::None)
+        .then(|| Scaler::fit::<B::InnerBackend, I, _>(&training_dataset, config.normalization));
+    train_regression_scaled::<B, T, I, DT, DV, W>(
+        artifact_dir,
+        training_dataset,
+        validation_dataset,
+        scaler,
+        TrainingEpochs::Shuffled,
+        config,
+        device,
+    )
+}
+
+/// How the training data loader moves from one epoch to the next.
+enum TrainingEpochs {
+    /// The dataset shuffles itself, such as a [`BlockShuffledDataset`]
+    Unshuffled,
+    /// The data loader reshuffles the dataset every epoch
+    Shuffled,
+    /// The data loader reshuffles a [`StreamingDataset`] and moves it to the next epoch
+    Streamed(StreamEpoch),
+}
+
+/// Trains with inputs and targets normalized by `scaler`.
+fn train_regression_scaled<B, T, I, DT, DV, W>(
+    artifact_dir: &str,
+    training_dataset: DT,
+    validation_dataset: DV,
+    scaler: Option<Scaler>,
+    epochs: TrainingEpochs,
+    config: TrainingConfig<T::Config>,
+    device: B::Device,
+) -> Statistics
+where
+    B: AutodiffBackend,
+    T: Model<B> + AutodiffModule<B>,
+    TrainingModel<T, B>: TrainStep<RegressionBatch<B, 3, 2>, RegressionOutput<B>>,
+    <TrainingModel<T, B> as AutodiffModule<B>>::InnerModule:
+        ValidStep<RegressionBatch<B::InnerBackend, 3, 2>, RegressionOutput<B::InnerBackend>>,
+    I: Send
+        + Sync
+        + Clone
+        + Debug
+        + Into<(Tensor<B, 2>, Tensor<B, 1>)>
+        + Into<(Tensor<B::InnerBackend, 2>, Tensor<B::InnerBackend, 1>)>
+        + 'static,
+    DT: Dataset<I> + 'static,
+    DV: Dataset<I> + 'static,
+    W: Weighting,
+    W::Batcher<B>: Batcher<I, RegressionBatch<B, 3, 2>>,
+    W::Batcher<B::InnerBackend>: Batcher<I, RegressionBatch<B::InnerBackend, 3, 2>>,
 {
     std::fs::create_dir_all(artifact_dir).expect("artifact dir should be creatable");
     config
@@ -311,21 +738,36 @@ where
         .expect("Config should be saved successfully");
 
     B::seed(config.seed);
+    let validation_dataset = Arc::new(validation_dataset);
+
+    let mut batcher_train = RegressionBatcher::<B>::new(device.clone())
+        .with_augmentations(config.augmentations.clone(), config.seed);
+    let mut batcher_valid = RegressionBatcher::<B::InnerBackend>::new(device.clone());
+    let scaler = scaler.map(Arc::new);
+    if let Some(scaler) = &scaler {
+        scaler
+            .save_to(Path::new(artifact_dir))
+            .expect("Scaler should be saved successfully");
+        batcher_train = batcher_train.with_scaler(scaler.clone());
+        batcher_valid = batcher_valid.with_scaler(scaler.clone());
+    }
 
-    let batcher_train = RegressionBatcher::<B>::new(device.clone());
-    let batcher_valid = RegressionBatcher::<B::InnerBackend>::new(device.clone());
-
-    let dataloader_train = DataLoaderBuilder::<I, _>::new(batcher_train)
+    let mut dataloader_train = DataLoaderBuilder::<I, _>::new(W::Batcher::<B>::from(batcher_train))
         .batch_size(config.batch_size)
-        .shuffle(config.seed)
-        .num_workers(config.num_workers)
-        .build(AcademyDataset::new(training_data_path, max_memory_usage));
+        .num_workers(config.num_workers);
+    if !matches!(epochs, TrainingEpochs::Unshuffled) {
+        dataloader_train = dataloader_train.shuffle(config.seed);
+    }
+    let mut dataloader_train = dataloader_train.build(training_dataset);
+    if let TrainingEpochs::Streamed(epoch) = epochs {
+        dataloader_train = Arc::new(EpochDataLoader::new(dataloader_train, epoch));
+    }
 
+    let batcher_valid = W::Batcher::<B::InnerBackend>::from(batcher_valid);
     let dataloader_test = DataLoaderBuilder::<I, _>::new(batcher_valid)
         .batch_size(config.batch_size)
-        .shuffle(config.seed)
         .num_workers(config.num_workers)
-        .build(AcademyDataset::new(testing_data_path.clone(), max_memory_usage));
+        .build(validation_dataset.clone());
 
     let model = T::from_config(config.model_config);
     let num_params = model.num_params();
@@ -392,20 +834,21 @@ where
 
     let model_trained = learner.fit(dataloader_train, dataloader_test);
 
-    let valid_dataset = AcademyDataset::<I>::new(testing_data_path, max_memory_usage);
-
-    let items: Vec<_> = (0..valid_dataset.len())
+    let items: Vec<_> = (0..validation_dataset.len())
         .into_par_iter()
         .map(|i|
-            valid_dataset.get(i).unwrap()
+            validation_dataset.get(i).unwrap()
         )
         .collect();
 
-    let batcher_valid = RegressionBatcher::<B::InnerBackend>::new(device);
-    let losses = model_trained.valid().step(batcher_valid.batch(items));
-    let (var, mean) = losses.loss.var_mean(0);
-    let loss_std_dev = var.into_scalar().to_f32().unwrap().sqrt();
-    let loss_mean = mean.into_scalar().to_f32().unwrap();
+    let mut batcher_valid = RegressionBatcher::<B::InnerBackend>::new(device);
+    if let Some(scaler) = scaler {
+        batcher_valid = batcher_valid.with_scaler(scaler);
+    }
+    let batch = W::Batcher::<B::InnerBackend>::from(batcher_valid).batch(items);
+    let weights = batch.weights.clone();
+    let losses = model_trained.valid().step(batch);
+    let stats = Statistics::from_outputs(losses.output, losses.targets, weights);
 
     model_trained
         .save_file(
@@ -414,14 +857,58 @@ where
         )
         .expect("Trained model should be saved successfully");
 
-    let stats = Statistics { loss_mean, loss_std_dev };
-
     stats
         .save(Path::new(artifact_dir).join("statistics.json"))
         .expect("Statistics file should be creatable");
     stats
 }
 
+/// Writes the outputs of `model` for every item of `dataset` into an npy array of shape
+/// `(n, outputs)` at `path`, running `batch_size` items through the model at a time.
+///
+/// If `batcher` has a scaler, such as the one loaded with [`Scaler::load_from`] from the artifact
+/// dir of the model, the outputs are mapped back to the units of the dataset.
+pub fn export_predictions<B, T, I, D>(
+    model: &T,
+    dataset: &D,
+    batcher: &RegressionBatcher<B>,
+    batch_size: usize,
+    path: &Path,
+) -> Result<(), DatasetError>
+where
+    B: Backend,
+    T: Model<B, Input = Tensor<B, 3>, Output = Tensor<B, 2>>,
+    I: Into<(Tensor<B, 2>, Tensor<B, 1>)>,
+    D: Dataset<I>,
+{
+    let mut writer = None;
+
+    for start in (0..dataset.len()).step_by(batch_size.max(1)) {
+        let end = (start + batch_size.max(1)).min(dataset.len());
+        let items: Vec<I> = (start..end).filter_map(|i| dataset.get(i)).collect();
+        let mut outputs = model.forward(batcher.batch(items).inputs);
+        if let Some(scaler) = batcher.scaler() {
+            outputs = scaler.denormalize_targets(outputs);
+        }
+        let [_, output_len] = outputs.dims();
+        let outputs = outputs.into_data().convert::<f32>().value;
+
+        let writer = match &mut writer {
+            Some(writer) => writer,
+            None => writer.insert(NpyWriter::create(path, &[output_len])?),
+        };
+        for output in outputs.chunks_exact(output_len.max(1)) {
+            writer.write_row(output)?;
+        }
+    }
+
+    match writer {
+        Some(writer) => writer.finish()?,
+        None => NpyWriter::create(path, &[0])?.finish()?,
+    };
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SuperTrainingConfig<T> {
     pub model_config: T,
@@ -452,6 +939,18 @@ pub struct SuperTrainingConfig<T> {
     pub max_grad_clipping_step: usize,
     #[serde(default = "default_grad_clipping_step_size")]
     pub grad_clipping_step_size: f32,
+    #[serde(default = "default_shuffle_window")]
+    pub shuffle_window: usize,
+    /// How many blocks past the current shuffle window to load in the background. 0 disables
+    /// prefetching.
+    #[serde(default)]
+    pub prefetch_blocks: usize,
+    /// See [`TrainingConfig::normalization`].
+    #[serde(default)]
+    pub normalization: Normalization,
+    /// See [`TrainingConfig::augmentations`].
+    #[serde(default)]
+    pub augmentations: Augmentations,
 }
 
 fn default_min_batch_pow() -> u32 {
@@ -508,6 +1007,7 @@ pub fn super_train_regression<B, T, I, TC, TCI>(
         + Into<(Tensor<B, 2>, Tensor<B, 1>)>
         + Into<(Tensor<B::InnerBackend, 2>, Tensor<B::InnerBackend, 1>)>
         + DeserializeOwned
+        + DatasetItem
         + 'static,
     TC: IntoIterator<IntoIter = TCI>,
     TCI: ExactSizeIterator + Iterator<Item = T::Config>,
@@ -571,6 +1071,10 @@ pub fn super_train_regression<B, T, I, TC, TCI>(
                                         stop_condition_epochs: config.stop_condition_epochs,
                                         learning_rate_warmup_steps: config
                                             .learning_rate_warmup_steps,
+                                        shuffle_window: config.shuffle_window,
+                                        prefetch_blocks: config.prefetch_blocks,
+                                        normalization: config.normalization,
+                                        augmentations: config.augmentations.clone(),
                                     };
                                     configs.push(config);
                                 });
@@ -608,5 +1112,102 @@ pub fn super_train_regression<B, T, I, TC, TCI>(
         secs = elapsed % 3600 % 60;
         writeln!(log_file, "[{hours}:{mins}:{secs}] Loss Mean: {:.5}, Loss σ: {:.5}", stats.loss_mean, stats.loss_std_dev)
             .expect("log file should be writable");
+        if let (Some(mean), Some(std_dev)) = (stats.weighted_loss_mean, stats.weighted_loss_std_dev) {
+            writeln!(log_file, "[{hours}:{mins}:{secs}] Weighted Loss Mean: {mean:.5}, Weighted Loss σ: {std_dev:.5}")
+                .expect("log file should be writable");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{
+        data::dataloader::batcher::Batcher,
+        tensor::{Data, Tensor},
+    };
+    use burn_ndarray::NdArray;
+
+    use crate::{data::RegressionItem, RegressionBatcher, Statistics, WeightedRegressionBatcher};
+
+    #[test]
+    fn weighted_loss_01() {
+        let item = |targets: f32, weight| RegressionItem {
+            inputs: vec![0.0, 1.0],
+            input_shape: [1, 2],
+            targets: vec![targets],
+            weight,
+        };
+        // Weights are only read when asked for
+        let plain = RegressionBatcher::<NdArray>::new(Default::default());
+        assert!(plain.batch(vec![item(1.0, Some(3.0))]).weights.is_none());
+
+        let batcher = WeightedRegressionBatcher::new(plain);
+        let batch = batcher.batch(vec![item(1.0, Some(3.0)), item(2.0, None)]);
+        assert_eq!(
+            batch.weights.clone().unwrap().into_data(),
+            Data::from([3.0, 1.0])
+        );
+        // Squared errors of 1 and 4, the first counting three times
+        let output = Tensor::<NdArray, 2>::from_floats([[0.0], [0.0]]);
+        assert_eq!(batch.mse_loss(output).into_scalar(), 7.0 / 4.0);
+
+        let unweighted = batcher.batch(vec![item(1.0, None), item(2.0, None)]);
+        assert!(unweighted.weights.is_none());
+        let output = Tensor::<NdArray, 2>::from_floats([[0.0], [0.0]]);
+        assert_eq!(unweighted.mse_loss(output).into_scalar(), 2.5);
+    }
+
+    #[test]
+    fn weighted_statistics_01() {
+        let output = Tensor::<NdArray, 2>::from_floats([[0.0], [0.0]]);
+        let targets = Tensor::<NdArray, 2>::from_floats([[1.0], [3.0]]);
+        let weights = Tensor::<NdArray, 1>::from_floats([3.0, 1.0]);
+        let stats = Statistics::from_outputs(output, targets, Some(weights));
+        // Squared errors of 1 and 9
+        assert_eq!(stats.loss_mean, 5.0);
+        assert_eq!(stats.loss_std_dev, 32f32.sqrt());
+        assert_eq!(stats.weighted_loss_mean, Some(3.0));
+        assert_eq!(stats.weighted_loss_std_dev, Some(12f32.sqrt()));
+        assert_ne!(stats.weighted_loss_mean, Some(stats.loss_mean));
+    }
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn mapped_batch_01() {
+        use burn::data::dataset::Dataset;
+
+        use crate::data::{create_mapped_dataset, MappedDataset};
+
+        let dir = tempfile::tempdir().unwrap();
+        create_mapped_dataset(
+            dir.path().into(),
+            [2, 2],
+            1,
+            (0..6).map(|i| (vec![i as f32; 4], [-(i as f32)])),
+        );
+        let dataset = MappedDataset::new(dir.path().into());
+
+        let batcher = RegressionBatcher::<NdArray>::new(Default::default());
+        let items: Vec<_> = (1..4).filter_map(|i| dataset.get(i)).collect();
+        let batch = batcher.batch(items.clone());
+        let mapped = batcher.batch_mapped(&dataset, 1..4).unwrap();
+        assert_eq!(batch.inputs.into_data(), mapped.inputs.into_data());
+        assert_eq!(batch.targets.into_data(), mapped.targets.into_data());
+        assert!(mapped.weights.is_none());
+        assert!(batcher.batch_mapped(&dataset, 4..7).is_none());
+
+        let weighted = WeightedRegressionBatcher::new(batcher).batch(items.clone());
+        assert!(weighted.weights.is_none());
+
+        // Both paths augment the same inputs the same way
+        let jitter = crate::data::Augmentations {
+            jitter_probability: 1.0,
+            ..Default::default()
+        };
+        let batcher =
+            RegressionBatcher::<NdArray>::new(Default::default()).with_augmentations(jitter, 3);
+        let batch = batcher.batch(items);
+        let mapped = batcher.batch_mapped(&dataset, 1..4).unwrap();
+        assert_eq!(batch.inputs.into_data(), mapped.inputs.into_data());
     }
 }